@@ -7,3 +7,21 @@ log-callback-err = WS message processing callback error occurred
 log-callback-timeout = WS message processing callback timeout
 log-invoke-handler = Packet received from target {$target}, deliver it to callback
 log-send-request = Sending Request packet to {$address}
+
+notice-reaction-added = {$name} reacted with {$emoji}
+notice-reaction-removed = {$name} removed the reaction {$emoji}
+notice-member-joined = {$name} joined
+notice-member-left = {$name} left
+notice-member-kicked = {$name} was removed
+notice-member-kicked-by = {$name} was removed by {$operator}
+notice-member-renamed = {$previous} is now known as {$name}
+notice-member-avatar-changed = {$name} changed their avatar
+
+render-image = Image
+render-sticker = Sticker
+render-audio = Audio
+render-video = Video
+render-file = File
+render-everyone = all
+render-forward = Forwarded messages
+render-unknown = Unsupported message
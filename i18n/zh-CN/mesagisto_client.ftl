@@ -7,3 +7,21 @@ log-callback-err = 消息处理回调发生错误
 log-callback-timeout = 消息处理回调超时
 log-invoke-handler = 收到目标{$target}的数据包, 将其传递给回调
 log-send-request = 正在向{$address}发送Request数据包
+
+notice-reaction-added = {$name} 回应了 {$emoji}
+notice-reaction-removed = {$name} 撤回了回应 {$emoji}
+notice-member-joined = {$name} 加入了群聊
+notice-member-left = {$name} 退出了群聊
+notice-member-kicked = {$name} 被移出了群聊
+notice-member-kicked-by = {$name} 被 {$operator} 移出了群聊
+notice-member-renamed = {$previous} 改名为 {$name}
+notice-member-avatar-changed = {$name} 更换了头像
+
+render-image = 图片
+render-sticker = 表情
+render-audio = 语音
+render-video = 视频
+render-file = 文件
+render-everyone = 全体成员
+render-forward = 转发的消息
+render-unknown = 不支持的消息
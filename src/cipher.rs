@@ -1,45 +1,645 @@
-use core::slice::SlicePattern;
-use std::ops::Deref;
+use std::{
+  sync::atomic::{AtomicU32, AtomicU8, Ordering},
+  time::{Duration, Instant},
+};
 
-use aes::Aes256;
-use aes_gcm_siv::{aead::Aead, Aes256GcmSiv, AesGcmSiv, KeyInit};
+use aes_gcm_siv::{aead::Aead, Aes256GcmSiv, KeyInit};
 use arcstr::ArcStr;
 use bytes::Bytes;
-use color_eyre::eyre::Result;
+use chacha20poly1305::ChaCha20Poly1305;
+use color_eyre::eyre::{bail, eyre, Error, Result};
+use dashmap::{mapref::one::Ref, DashMap};
+use hkdf::Hkdf;
 use lateinit::LateInit;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use zeroize::Zeroizing;
 
-use crate::data::Payload;
+use crate::{
+  client::DEFAULT_CLIENT,
+  data::{message::FileSecret, Payload},
+  secret::Secret,
+  MesagistoConfig, OkExt, NAMESPACE_MSGIST,
+};
 
-#[derive(Singleton, Default)]
-pub struct Cipher {
-  inner: LateInit<AesGcmSiv<Aes256>>,
-  pub key: LateInit<[u8; 32]>,
-  pub nonce: LateInit<aes_gcm_siv::Nonce>,
-  pub origin_key: LateInit<ArcStr>,
+/// Version byte leading every envelope produced by [`Cipher::seal`].
+pub const PACKET_VERSION: u8 = 3;
+/// Envelope without a cipher suite, sealed with AES-256-GCM-SIV.
+const KEYED_VERSION: u8 = 2;
+/// Envelope without a key id, still produced by older bridges.
+const NONCE_ONLY_VERSION: u8 = 1;
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const ROOM_KEY_INFO: &[u8] = b"mesagisto room key";
+const ROOM_ADDRESS_INFO: &[u8] = b"mesagisto room address";
+
+/// AEAD algorithm a packet is sealed with, recorded in its header.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum CipherSuite {
+  #[default]
+  Aes256GcmSiv     = 0,
+  /// Faster than AES on hardware without AES instructions.
+  ChaCha20Poly1305 = 1,
+}
+impl TryFrom<u8> for CipherSuite {
+  type Error = Error;
+
+  fn try_from(value: u8) -> Result<Self> {
+    match value {
+      0 => Ok(Self::Aes256GcmSiv),
+      1 => Ok(Self::ChaCha20Poly1305),
+      _ => bail!("Unknown cipher suite {}", value),
+    }
+  }
+}
+
+pub enum SuiteCipher {
+  Aes256GcmSiv(Box<Aes256GcmSiv>),
+  ChaCha20Poly1305(ChaCha20Poly1305),
+}
+impl SuiteCipher {
+  pub fn new(suite: CipherSuite, key: &[u8]) -> Result<Self> {
+    match suite {
+      CipherSuite::Aes256GcmSiv => Self::Aes256GcmSiv(Aes256GcmSiv::new_from_slice(key)?.into()),
+      CipherSuite::ChaCha20Poly1305 => {
+        Self::ChaCha20Poly1305(ChaCha20Poly1305::new_from_slice(key)?)
+      }
+    }
+    .ok()
+  }
+
+  pub fn encrypt(&self, nonce: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    match self {
+      Self::Aes256GcmSiv(cipher) => cipher.encrypt(nonce.into(), plaintext),
+      Self::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), plaintext),
+    }
+    .map_err(|e| eyre!("Failed to encrypt: {}", e))
+  }
+
+  pub fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    match self {
+      Self::Aes256GcmSiv(cipher) => cipher.decrypt(nonce.into(), ciphertext),
+      Self::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), ciphertext),
+    }
+    .map_err(|e| eyre!("Failed to decrypt: {}", e))
+  }
+}
+
+/// How a human-chosen passphrase is stretched into a 256-bit key.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "t")]
+pub enum Kdf {
+  /// Unsalted SHA-256, only kept for networks set up before salted
+  /// derivation existed.
+  #[default]
+  Sha256,
+  Argon2id {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+  },
+  Scrypt {
+    log_n: u8,
+    r: u32,
+    p: u32,
+  },
+}
+impl Kdf {
+  /// Argon2id with the parameters recommended by OWASP.
+  pub const ARGON2ID: Kdf = Kdf::Argon2id {
+    memory_kib: 19 * 1024,
+    iterations: 2,
+    parallelism: 1,
+  };
+  pub const SCRYPT: Kdf = Kdf::Scrypt {
+    log_n: 17,
+    r: 8,
+    p: 1,
+  };
+
+  pub fn derive(&self, passphrase: &[u8], salt: Option<&[u8]>) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    match (self, salt) {
+      (Kdf::Sha256, _) => *key = Sha256::digest(passphrase).into(),
+      (_, None) => bail!("{:?} needs a network-wide cipher salt", self),
+      (
+        Kdf::Argon2id {
+          memory_kib,
+          iterations,
+          parallelism,
+        },
+        Some(salt),
+      ) => {
+        let params = argon2::Params::new(*memory_kib, *iterations, *parallelism, Some(key.len()))
+          .map_err(|e| eyre!("Invalid Argon2id parameters: {}", e))?;
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+          .hash_password_into(passphrase, salt, &mut *key)
+          .map_err(|e| eyre!("Argon2id failed: {}", e))?;
+      }
+      (Kdf::Scrypt { log_n, r, p }, Some(salt)) => {
+        let params = scrypt::Params::new(*log_n, *r, *p, key.len())
+          .map_err(|e| eyre!("Invalid scrypt parameters: {}", e))?;
+        scrypt::scrypt(passphrase, salt, &params, &mut *key)
+          .map_err(|e| eyre!("scrypt failed: {}", e))?;
+      }
+    }
+    Ok(key)
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct KeyDerivation {
+  pub kdf: Kdf,
+  pub salt: Option<ArcStr>,
+}
+impl KeyDerivation {
+  pub fn derive(&self, passphrase: &Secret) -> Result<Zeroizing<[u8; 32]>> {
+    self.kdf.derive(
+      passphrase.expose().as_bytes(),
+      self.salt.as_ref().map(|salt| salt.as_bytes()),
+    )
+  }
+}
+
+pub struct Key {
+  /// Short identifier carried in every packet sealed with this key.
+  pub id: u32,
+  /// Moment after which the key is no longer accepted for decryption.
+  pub expires_at: Option<Instant>,
+  key: Zeroizing<[u8; 32]>,
+  inner: Aes256GcmSiv,
+  legacy_nonce: aes_gcm_siv::Nonce,
+  rooms: DashMap<(Uuid, CipherSuite), SuiteCipher>,
 }
+impl Key {
+  pub fn new(hash_key: Zeroizing<[u8; 32]>) -> Result<Self> {
+    let id = u32::from_be_bytes(Sha256::digest(*hash_key)[..KEY_ID_LEN].try_into()?);
+    Self {
+      id,
+      expires_at: None,
+      inner: Aes256GcmSiv::new_from_slice(&*hash_key)?,
+      legacy_nonce: *aes_gcm_siv::Nonce::from_slice(&hash_key[..NONCE_LEN]),
+      key: hash_key,
+      rooms: Default::default(),
+    }
+    .ok()
+  }
 
-impl Deref for Cipher {
-  type Target = AesGcmSiv<Aes256>;
+  /// Sub-key of `room` for `suite` derived with HKDF-SHA256, cached after the
+  /// first use.
+  pub fn for_room(
+    &self,
+    room: &Uuid,
+    suite: CipherSuite,
+  ) -> Result<Ref<'_, (Uuid, CipherSuite), SuiteCipher>> {
+    if let Some(cipher) = self.rooms.get(&(*room, suite)) {
+      return Ok(cipher);
+    }
+    let mut info = ROOM_KEY_INFO.to_vec();
+    info.extend_from_slice(room.as_bytes());
+    // AES-256-GCM-SIV keeps the derivation it shipped with before suites existed
+    if suite != CipherSuite::Aes256GcmSiv {
+      info.push(suite as u8);
+    }
+    let mut room_key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, &*self.key)
+      .expand(&info, &mut *room_key)
+      .map_err(|e| eyre!("HKDF expand failed: {}", e))?;
+    let cipher = SuiteCipher::new(suite, &*room_key)?;
+    Ok(
+      self
+        .rooms
+        .entry((*room, suite))
+        .or_insert(cipher)
+        .downgrade(),
+    )
+  }
 
-  fn deref(&self) -> &Self::Target {
-    &self.inner
+  pub fn is_live(&self) -> bool {
+    !matches!(self.expires_at, Some(at) if at <= Instant::now())
   }
 }
 
+pub static CIPHER: Lazy<&'static Cipher> = Lazy::new(|| &DEFAULT_CLIENT.cipher);
+
+#[derive(Default)]
+pub struct Cipher {
+  keys: DashMap<u32, Key>,
+  active: AtomicU32,
+  suite: AtomicU8,
+  /// Key the room addresses are derived from, it stays put across rotations.
+  pub origin_key: LateInit<Secret>,
+  pub derivation: LateInit<KeyDerivation>,
+  /// `origin_key` stretched by a salted KDF, absent for [`Kdf::Sha256`].
+  address_key: LateInit<Option<Zeroizing<[u8; 32]>>>,
+}
+
 impl Cipher {
-  pub fn init(&self, key: &ArcStr) -> Result<()> {
-    use sha2::{Digest, Sha256};
-    self.origin_key.init(key.to_owned());
-    let hash_key = Sha256::digest(key).into();
-    self.key.init(hash_key);
-    let nonce = aes_gcm_siv::Nonce::from_slice(hash_key[0..12].try_into().unwrap()).to_owned();
-    self.nonce.init(nonce);
-    let cipher = Aes256GcmSiv::new_from_slice(self.key.as_slice())?;
-    self.inner.init(cipher);
+  pub fn init(&self, key: &Secret) -> Result<()> {
+    self.init_with(key, KeyDerivation::default())
+  }
+
+  pub fn init_with(&self, key: &Secret, derivation: KeyDerivation) -> Result<()> {
+    let address_key = match derivation.kdf {
+      Kdf::Sha256 => None,
+      _ => Some(derivation.derive(key)?),
+    };
+    self.origin_key.init(key.clone());
+    self.address_key.init(address_key);
+    self.derivation.init(derivation);
+    self.rotate(key, Duration::ZERO)?;
+    Ok(())
+  }
+
+  /// Sets up the keyring `config` describes. With an `origin_key` the bridge
+  /// sends with `cipher_key` while packets sealed with the origin key are
+  /// still accepted for `key_grace`, just like the `retired_keys`.
+  pub fn init_keyring(&self, config: &MesagistoConfig) -> Result<()> {
+    let derivation = KeyDerivation {
+      kdf: config.kdf.clone(),
+      salt: config.cipher_salt.clone(),
+    };
+    match &config.origin_key {
+      Some(origin_key) => {
+        self.init_with(origin_key, derivation)?;
+        self.rotate(&config.cipher_key, config.key_grace)?;
+      }
+      None => self.init_with(&config.cipher_key, derivation)?,
+    }
+    for key in &config.retired_keys {
+      self.retire(key, config.key_grace)?;
+    }
     Ok(())
   }
-  pub fn decrypt_payload(&self, payload: Bytes) -> Result<Payload> {
-    let plain = self.decrypt(&self.nonce, payload.as_slice())?;
+
+  /// Makes `key` the one new packets are sealed with. The previously active
+  /// key keeps decrypting for `grace` so that bridges can be switched over one
+  /// at a time.
+  pub fn rotate(&self, key: &Secret, grace: Duration) -> Result<u32> {
+    let key = Key::new(self.derivation.derive(key)?)?;
+    let id = key.id;
+    self.keys.insert(id, key);
+    let previous = self.active.swap(id, Ordering::AcqRel);
+    if previous != id {
+      if let Some(mut previous) = self.keys.get_mut(&previous) {
+        previous.expires_at = Some(Instant::now() + grace);
+      }
+    }
+    self.keys.retain(|_, key| key.is_live());
+    Ok(id)
+  }
+
+  /// Accepts packets sealed with `key` for `grace` from now on without using
+  /// it for sending.
+  pub fn retire(&self, key: &Secret, grace: Duration) -> Result<u32> {
+    let mut key = Key::new(self.derivation.derive(key)?)?;
+    let id = key.id;
+    if id == self.active.load(Ordering::Acquire) {
+      return Ok(id);
+    }
+    key.expires_at = Some(Instant::now() + grace);
+    self.keys.insert(id, key);
+    self.keys.retain(|_, key| key.is_live());
+    Ok(id)
+  }
+
+  /// Subject `room_address` is published on. With a salted KDF it comes from
+  /// the stretched origin key, so the subject gives nothing away for guessing
+  /// the passphrase offline. Switching a network from [`Kdf::Sha256`] moves
+  /// every room to a new subject.
+  pub fn room_id(&self, room_address: &str) -> Uuid {
+    match &*self.address_key {
+      Some(address_key) => {
+        let mut info = ROOM_ADDRESS_INFO.to_vec();
+        info.extend_from_slice(room_address.as_bytes());
+        let mut hashed = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &**address_key)
+          .expand(&info, &mut hashed)
+          .unwrap();
+        Uuid::new_v5(&NAMESPACE_MSGIST, &hashed)
+      }
+      None => {
+        let unique_address =
+          Zeroizing::new(format!("{}{}", room_address, self.origin_key.expose()));
+        Uuid::new_v5(&NAMESPACE_MSGIST, unique_address.as_bytes())
+      }
+    }
+  }
+
+  pub fn active_key_id(&self) -> u32 {
+    self.active.load(Ordering::Acquire)
+  }
+
+  /// Selects the suite new packets are sealed with. Packets are always opened
+  /// with the suite their header declares.
+  pub fn set_suite(&self, suite: CipherSuite) {
+    self.suite.store(suite as u8, Ordering::Release);
+  }
+
+  pub fn suite(&self) -> CipherSuite {
+    CipherSuite::try_from(self.suite.load(Ordering::Acquire)).unwrap_or_default()
+  }
+
+  /// Encrypts `plaintext` with the sub-key of `room` derived from the active
+  /// key under a fresh random nonce, and wraps it as
+  /// `version || suite || key id || nonce || ciphertext`.
+  pub fn seal(&self, room: &Uuid, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let key = self
+      .keys
+      .get(&self.active_key_id())
+      .ok_or_else(|| eyre!("Cipher has not been initialized"))?;
+    let suite = self.suite();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = key.for_room(room, suite)?.encrypt(&nonce, plaintext)?;
+    let mut sealed = Vec::with_capacity(2 + KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+    sealed.push(PACKET_VERSION);
+    sealed.push(suite as u8);
+    sealed.extend_from_slice(&key.id.to_be_bytes());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+  }
+
+  /// Reverses [`Cipher::seal`]. Envelopes without a key id and bare
+  /// ciphertext under the legacy fixed nonce are still accepted, they are
+  /// tried against every key that is still live.
+  pub fn open(&self, room: &Uuid, sealed: &[u8]) -> Result<Vec<u8>> {
+    if let [PACKET_VERSION, suite, rest @ ..] = sealed {
+      if let Ok(suite) = CipherSuite::try_from(*suite) {
+        if let Some(plain) = self.open_keyed(room, suite, rest, false) {
+          return Ok(plain);
+        }
+      }
+    }
+    if let [KEYED_VERSION, rest @ ..] = sealed {
+      if let Some(plain) = self.open_keyed(room, CipherSuite::Aes256GcmSiv, rest, true) {
+        return Ok(plain);
+      }
+    }
+    if let [NONCE_ONLY_VERSION, rest @ ..] = sealed {
+      if rest.len() > NONCE_LEN {
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = aes_gcm_siv::Nonce::from_slice(nonce);
+        if let Some(plain) = self.try_live_keys(|key| key.inner.decrypt(nonce, ciphertext).ok()) {
+          return Ok(plain);
+        }
+      }
+    }
+    match self.try_live_keys(|key| key.inner.decrypt(&key.legacy_nonce, sealed).ok()) {
+      Some(plain) => Ok(plain),
+      None => bail!("None of the live cipher keys can decrypt the packet"),
+    }
+  }
+
+  /// Opens `key id || nonce || ciphertext`. Bridges predating per-room keys
+  /// sealed with the key itself, `root_fallback` tries that too.
+  fn open_keyed(
+    &self,
+    room: &Uuid,
+    suite: CipherSuite,
+    rest: &[u8],
+    root_fallback: bool,
+  ) -> Option<Vec<u8>> {
+    if rest.len() <= KEY_ID_LEN + NONCE_LEN {
+      return None;
+    }
+    let (id, rest) = rest.split_at(KEY_ID_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let id = u32::from_be_bytes(id.try_into().ok()?);
+    let key = self.keys.get(&id).filter(|key| key.is_live())?;
+    let plain = key.for_room(room, suite).ok()?.decrypt(nonce, ciphertext);
+    match plain {
+      Ok(plain) => Some(plain),
+      Err(_) if root_fallback => key
+        .inner
+        .decrypt(aes_gcm_siv::Nonce::from_slice(nonce), ciphertext)
+        .ok(),
+      Err(_) => None,
+    }
+  }
+
+  fn try_live_keys<F>(&self, decrypt: F) -> Option<Vec<u8>>
+  where
+    F: Fn(&Key) -> Option<Vec<u8>>,
+  {
+    self
+      .keys
+      .iter()
+      .filter(|key| key.is_live())
+      .find_map(|key| decrypt(&key))
+  }
+
+  pub fn decrypt_payload(&self, room: &Uuid, payload: Bytes) -> Result<Payload> {
+    let plain = self.open(room, &payload)?;
     Payload::from_cbor(plain.as_slice())
   }
 }
+
+/// Encrypts an attachment under a fresh random key, returning
+/// `nonce || ciphertext` and the secret to put into the message segment.
+pub fn seal_file(plaintext: &[u8]) -> Result<(Vec<u8>, FileSecret)> {
+  let key = Zeroizing::new(rand::random::<[u8; 32]>());
+  let nonce: [u8; NONCE_LEN] = rand::random();
+  let ciphertext = Aes256GcmSiv::new_from_slice(&*key)?
+    .encrypt(aes_gcm_siv::Nonce::from_slice(&nonce), plaintext)?;
+  let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+  sealed.extend_from_slice(&nonce);
+  sealed.extend_from_slice(&ciphertext);
+  let secret = FileSecret {
+    key: Zeroizing::new(key.to_vec()),
+    hash: Sha256::digest(plaintext).to_vec(),
+  };
+  Ok((sealed, secret))
+}
+
+/// Reverses [`seal_file`] and checks the content against the hash.
+pub fn open_file(secret: &FileSecret, sealed: &[u8]) -> Result<Vec<u8>> {
+  if sealed.len() < NONCE_LEN {
+    bail!("Encrypted file is truncated");
+  }
+  let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+  let plaintext = Aes256GcmSiv::new_from_slice(&secret.key)?
+    .decrypt(aes_gcm_siv::Nonce::from_slice(nonce), ciphertext)?;
+  if Sha256::digest(&plaintext).as_slice() != secret.hash {
+    bail!("Decrypted file does not match its hash");
+  }
+  Ok(plaintext)
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use aes_gcm_siv::{aead::Aead, Aes256GcmSiv, KeyInit};
+  use sha2::{Digest, Sha256};
+  use uuid::Uuid;
+
+  use crate::{
+    cipher::{open_file, seal_file, Cipher, CipherSuite, Kdf, KeyDerivation, KEYED_VERSION},
+    MesagistoConfig, NAMESPACE_MSGIST,
+  };
+  #[test]
+  fn test() {
+    let room = Uuid::new_v4();
+    let cipher = Cipher::default();
+    cipher.init(&"this is key".into()).unwrap();
+
+    let first = cipher.seal(&room, b"payload").unwrap();
+    let second = cipher.seal(&room, b"payload").unwrap();
+    assert_ne!(first, second);
+    assert_eq!(cipher.open(&room, &first).unwrap(), b"payload");
+    assert_eq!(cipher.open(&room, &second).unwrap(), b"payload");
+
+    let hash_key = Sha256::digest("this is key");
+    let legacy = Aes256GcmSiv::new(&hash_key)
+      .encrypt(
+        aes_gcm_siv::Nonce::from_slice(&hash_key[..12]),
+        b"payload".as_ref(),
+      )
+      .unwrap();
+    assert_eq!(cipher.open(&room, &legacy).unwrap(), b"payload");
+  }
+
+  #[test]
+  fn test_room_keys() {
+    let (room, other_room) = (Uuid::new_v4(), Uuid::new_v4());
+    let cipher = Cipher::default();
+    cipher.init(&"this is key".into()).unwrap();
+
+    let sealed = cipher.seal(&room, b"payload").unwrap();
+    assert_eq!(cipher.open(&room, &sealed).unwrap(), b"payload");
+    assert!(cipher.open(&other_room, &sealed).is_err());
+
+    let key = cipher.keys.get(&cipher.active_key_id()).unwrap();
+    let nonce = [7u8; 12];
+    let mut root_sealed = vec![KEYED_VERSION];
+    root_sealed.extend_from_slice(&key.id.to_be_bytes());
+    root_sealed.extend_from_slice(&nonce);
+    root_sealed.extend(
+      key
+        .inner
+        .encrypt(aes_gcm_siv::Nonce::from_slice(&nonce), b"payload".as_ref())
+        .unwrap(),
+    );
+    assert_eq!(cipher.open(&other_room, &root_sealed).unwrap(), b"payload");
+  }
+
+  #[test]
+  fn test_suites() {
+    let room = Uuid::new_v4();
+    let sender = Cipher::default();
+    sender.init(&"this is key".into()).unwrap();
+    sender.set_suite(CipherSuite::ChaCha20Poly1305);
+    let receiver = Cipher::default();
+    receiver.init(&"this is key".into()).unwrap();
+
+    let chacha = sender.seal(&room, b"payload").unwrap();
+    assert_eq!(chacha[1], CipherSuite::ChaCha20Poly1305 as u8);
+    assert_eq!(receiver.open(&room, &chacha).unwrap(), b"payload");
+    let aes = receiver.seal(&room, b"payload").unwrap();
+    assert_eq!(sender.open(&room, &aes).unwrap(), b"payload");
+  }
+
+  #[test]
+  fn test_kdf() {
+    let room = Uuid::new_v4();
+    let derivation = KeyDerivation {
+      kdf: Kdf::Argon2id {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+      },
+      salt: Some("network salt".into()),
+    };
+    let sender = Cipher::default();
+    sender
+      .init_with(&"this is key".into(), derivation.clone())
+      .unwrap();
+    let receiver = Cipher::default();
+    receiver
+      .init_with(&"this is key".into(), derivation)
+      .unwrap();
+    let unsalted = Cipher::default();
+    unsalted.init(&"this is key".into()).unwrap();
+
+    let sealed = sender.seal(&room, b"payload").unwrap();
+    assert_eq!(receiver.open(&room, &sealed).unwrap(), b"payload");
+    assert!(unsalted.open(&room, &sealed).is_err());
+
+    assert_eq!(sender.room_id("room"), receiver.room_id("room"));
+    assert_ne!(sender.room_id("room"), unsalted.room_id("room"));
+    assert_eq!(
+      unsalted.room_id("room"),
+      Uuid::new_v5(&NAMESPACE_MSGIST, b"roomthis is key")
+    );
+    assert!(Kdf::SCRYPT.derive(b"this is key", None).is_err());
+  }
+
+  #[test]
+  fn test_file() {
+    let (sealed, secret) = seal_file(b"image").unwrap();
+    assert_eq!(open_file(&secret, &sealed).unwrap(), b"image");
+    let debug = format!("{:?}", secret);
+    assert!(debug.contains("***") && !debug.contains(&format!("{:?}", *secret.key)));
+    let (other, _) = seal_file(b"image").unwrap();
+    assert!(open_file(&secret, &other).is_err());
+  }
+
+  #[test]
+  fn test_rotation() {
+    let cipher = Cipher::default();
+    cipher.init(&"old key".into()).unwrap();
+    let room = Uuid::new_v4();
+    let old = cipher.seal(&room, b"payload").unwrap();
+    let old_id = cipher.active_key_id();
+
+    cipher
+      .rotate(&"new key".into(), Duration::from_secs(60))
+      .unwrap();
+    assert_ne!(old_id, cipher.active_key_id());
+    let new = cipher.seal(&room, b"payload").unwrap();
+    assert_eq!(cipher.open(&room, &old).unwrap(), b"payload");
+    assert_eq!(cipher.open(&room, &new).unwrap(), b"payload");
+
+    let stale = Cipher::default();
+    stale.init(&"old key".into()).unwrap();
+    assert!(stale.open(&room, &new).is_err());
+
+    cipher.retire(&"old key".into(), Duration::ZERO).unwrap();
+    assert!(cipher.open(&room, &old).is_err());
+  }
+
+  #[test]
+  fn test_origin_key() {
+    let room = Uuid::new_v4();
+    let stale = Cipher::default();
+    stale.init(&"old key".into()).unwrap();
+    let old = stale.seal(&room, b"payload").unwrap();
+
+    let config = MesagistoConfig {
+      cipher_key: "new key".into(),
+      origin_key: Some("old key".into()),
+      key_grace: Duration::from_secs(60),
+      ..Default::default()
+    };
+    let cipher = Cipher::default();
+    cipher.init_keyring(&config).unwrap();
+    assert_eq!(cipher.origin_key.expose(), "old key");
+    assert_eq!(cipher.open(&room, &old).unwrap(), b"payload");
+    let new = cipher.seal(&room, b"payload").unwrap();
+    assert!(stale.open(&room, &new).is_err());
+
+    let cipher = Cipher::default();
+    cipher
+      .init_keyring(&MesagistoConfig {
+        key_grace: Duration::ZERO,
+        ..config
+      })
+      .unwrap();
+    assert!(cipher.open(&room, &old).is_err());
+  }
+}
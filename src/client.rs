@@ -0,0 +1,100 @@
+use std::sync::{Arc, Weak};
+
+use color_eyre::eyre::Result;
+use once_cell::sync::Lazy;
+use uuid::Uuid;
+
+use crate::{
+  cipher::Cipher,
+  data::{events::Event, Packet},
+  db::Db,
+  i18n::LANGUAGE_LOADER,
+  identity::Identity,
+  net::Net,
+  replay::Replay,
+  res::Res,
+  server::{PacketHandler, Server},
+  throttle::Throttle,
+  MesagistoConfig, OptionExt,
+};
+
+/// Instance the `CIPHER`, `SERVER`, `DB`, `RES`, `NET`, `IDENTITY`, `REPLAY`
+/// and `THROTTLE` statics point into, set up by [`MesagistoConfig::apply`].
+pub static DEFAULT_CLIENT: Lazy<Arc<MesagistoClient>> = Lazy::new(MesagistoClient::new);
+
+/// Membership in one Mesagisto network with its own key, NATS connection,
+/// database and resource cache. A process joins several networks by creating
+/// one client for each, their `name`s must differ.
+pub struct MesagistoClient {
+  pub cipher: Cipher,
+  pub identity: Identity,
+  pub replay: Replay,
+  pub db: Db,
+  pub net: Net,
+  pub server: Server,
+  pub res: Res,
+  pub throttle: Throttle,
+}
+impl MesagistoClient {
+  pub fn new() -> Arc<Self> {
+    Arc::new_cyclic(|client| Self {
+      cipher: Default::default(),
+      identity: Identity::new(client.clone()),
+      replay: Default::default(),
+      db: Default::default(),
+      net: Default::default(),
+      server: Server::new(client.clone()),
+      res: Res::new(client.clone()),
+      throttle: Default::default(),
+    })
+  }
+
+  /// Creates a client and joins the network described by `config`.
+  pub async fn connect(config: MesagistoConfig) -> Result<Arc<Self>> {
+    let client = Self::new();
+    client.init(config).await?;
+    Ok(client)
+  }
+
+  pub async fn init(&self, config: MesagistoConfig) -> Result<()> {
+    Lazy::force(&LANGUAGE_LOADER);
+    self.db.init(config.name.clone().some());
+    self.cipher.init_keyring(&config)?;
+    self.identity.init(config.name.clone(), config.trust)?;
+    self.cipher.set_suite(config.cipher_suite);
+    self
+      .replay
+      .init(config.replay_window, config.replay_cache_size);
+    self.throttle.init(config.ephemeral_interval);
+    self.res.init(&config.name).await;
+    self.server.init(config.remote_address).await?;
+    self.net.init(config.proxy);
+    Ok(())
+  }
+
+  /// Sends a typing or presence event unless it merely repeats the last one,
+  /// returning whether it was sent.
+  pub async fn send_ephemeral(&self, room: Uuid, event: Event) -> Result<bool> {
+    if !self.throttle.allow(&room, &event) {
+      return Ok(false);
+    }
+    let packet = Packet::new_with(self, room, event.into())?;
+    self.server.send(packet).await?;
+    Ok(true)
+  }
+
+  pub fn packet_handler<F>(&self, resolver: F)
+  where
+    F: PacketHandler,
+  {
+    let h = Box::new(resolver);
+    self.server.packet_handler.init(h);
+  }
+}
+
+/// Back-reference a component keeps to the client owning it.
+pub(crate) fn upgrade(client: &Weak<MesagistoClient>) -> Arc<MesagistoClient> {
+  client
+    .upgrade()
+    .expect("MesagistoClient has already been dropped")
+}
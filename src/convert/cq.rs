@@ -0,0 +1,226 @@
+use arcstr::ArcStr;
+use ciborium::Value;
+
+use crate::{
+  convert::{numeric_profile, placeholder, Dialect},
+  data::{
+    message::{MessageType, Profile},
+    rich::to_plain_text,
+  },
+};
+
+/// Prefix of the tag of [`MessageType::Unknown`] segments holding CQ codes
+/// without a counterpart, so QQ bridges can pass them on untouched.
+const UNKNOWN_PREFIX: &str = "cq:";
+
+/// CQ codes of OneBot (go-cqhttp and alike) QQ bridges.
+pub struct CqCode;
+impl CqCode {
+  pub fn escape(text: &str) -> String {
+    text
+      .replace('&', "&amp;")
+      .replace('[', "&#91;")
+      .replace(']', "&#93;")
+  }
+
+  pub fn escape_param(text: &str) -> String {
+    Self::escape(text).replace(',', "&#44;")
+  }
+
+  pub fn unescape(text: &str) -> String {
+    text
+      .replace("&#44;", ",")
+      .replace("&#91;", "[")
+      .replace("&#93;", "]")
+      .replace("&amp;", "&")
+  }
+
+  fn code(kind: &str, params: &[(&str, &str)]) -> String {
+    let mut code = format!("[CQ:{}", kind);
+    for (key, value) in params {
+      code.push_str(&format!(",{}={}", key, Self::escape_param(value)));
+    }
+    code.push(']');
+    code
+  }
+
+  fn segment(kind: &str, params: Vec<(String, String)>) -> MessageType {
+    let param = |key: &str| {
+      params
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.clone())
+    };
+    let file = param("file").unwrap_or_default();
+    let url = param("url").map(ArcStr::from);
+    match kind {
+      "at" => match param("qq").as_deref() {
+        Some("all") => return MessageType::MentionAll,
+        Some(qq) => {
+          if let Ok(id) = qq.parse::<i64>() {
+            let display = param("name").unwrap_or_else(|| qq.to_owned());
+            return MessageType::Mention {
+              profile: numeric_profile(id, param("name")),
+              display,
+            };
+          }
+        }
+        None => {}
+      },
+      "image" => {
+        return MessageType::Image {
+          id: file.into_bytes(),
+          url,
+          secret: None,
+        }
+      }
+      "record" => {
+        return MessageType::Audio {
+          id: file.into_bytes(),
+          url,
+          duration: None,
+          mime: None,
+          voice: true,
+          secret: None,
+        }
+      }
+      "video" => {
+        return MessageType::Video {
+          id: file.into_bytes(),
+          url,
+          duration: None,
+          width: None,
+          height: None,
+          size: None,
+          mime: None,
+          secret: None,
+          thumbnail: None,
+          thumbnail_secret: None,
+        }
+      }
+      _ => {}
+    }
+    let mut raw = vec![(
+      Value::Text("t".into()),
+      Value::Text(format!("{}{}", UNKNOWN_PREFIX, kind)),
+    )];
+    raw.extend(
+      params
+        .into_iter()
+        .map(|(key, value)| (Value::Text(key), Value::Text(value))),
+    );
+    MessageType::Unknown {
+      tag: format!("{}{}", UNKNOWN_PREFIX, kind),
+      raw: Value::Map(raw),
+    }
+  }
+
+  /// Writes an unknown segment back as the CQ code it was parsed from.
+  fn unknown(tag: &str, raw: &Value) -> Option<String> {
+    let kind = tag.strip_prefix(UNKNOWN_PREFIX)?;
+    let params = raw
+      .as_map()?
+      .iter()
+      .filter_map(|(key, value)| Some((key.as_text()?, value.as_text()?)))
+      .filter(|(key, _)| *key != "t")
+      .collect::<Vec<_>>();
+    Some(Self::code(kind, &params))
+  }
+}
+
+impl Dialect for CqCode {
+  fn parse(&self, text: &str) -> Vec<MessageType> {
+    let mut chain = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("[CQ:") {
+      let end = match rest[start..].find(']') {
+        Some(end) => start + end,
+        None => break,
+      };
+      if start > 0 {
+        chain.push(MessageType::Text {
+          content: Self::unescape(&rest[..start]),
+        });
+      }
+      let mut parts = rest[start + 4..end].split(',');
+      let kind = parts.next().unwrap_or_default();
+      let params = parts
+        .map(|param| {
+          let (key, value) = param.split_once('=').unwrap_or((param, ""));
+          (key.to_owned(), Self::unescape(value))
+        })
+        .collect();
+      chain.push(Self::segment(kind, params));
+      rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+      chain.push(MessageType::Text {
+        content: Self::unescape(rest),
+      });
+    }
+    chain
+  }
+
+  fn render(&self, chain: &[MessageType], mentions: &dyn Fn(&Profile) -> Option<String>) -> String {
+    let mut text = String::new();
+    for segment in chain {
+      match segment {
+        MessageType::Text { content } | MessageType::Edit { content } => {
+          text.push_str(&Self::escape(content))
+        }
+        MessageType::Rich { spans } => text.push_str(&Self::escape(&to_plain_text(spans))),
+        MessageType::Mention { profile, display } => match mentions(profile) {
+          Some(id) => text.push_str(&Self::code("at", &[("qq", &id)])),
+          None => text.push_str(&Self::escape(&format!("@{}", display))),
+        },
+        MessageType::MentionAll => text.push_str(&Self::code("at", &[("qq", "all")])),
+        MessageType::Image { url: Some(url), .. } | MessageType::Sticker { url: Some(url), .. } => {
+          text.push_str(&Self::code("image", &[("file", url)]))
+        }
+        MessageType::Audio {
+          url: Some(url),
+          voice: true,
+          ..
+        } => text.push_str(&Self::code("record", &[("file", url)])),
+        MessageType::Video { url: Some(url), .. } => {
+          text.push_str(&Self::code("video", &[("file", url)]))
+        }
+        MessageType::Unknown { tag, raw } => match Self::unknown(tag, raw) {
+          Some(code) => text.push_str(&code),
+          None => text.push_str(&Self::escape(&placeholder(segment))),
+        },
+        _ => text.push_str(&Self::escape(&placeholder(segment))),
+      }
+    }
+    text
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::{
+    convert::{cq::CqCode, numeric_id, Dialect},
+    data::message::MessageType,
+  };
+  #[test]
+  fn test() {
+    let text = "&#91;hi&#93; &amp; [CQ:at,qq=42] [CQ:at,qq=all][CQ:face,id=14]\
+                [CQ:share,url=https://example.com/?a=1&#44;2,title=x]";
+    let chain = CqCode.parse(text);
+    assert_eq!(CqCode.render(&chain, &numeric_id), text);
+    assert!(matches!(&chain[0], MessageType::Text { content } if content == "[hi] & "));
+    assert!(matches!(&chain[1], MessageType::Mention { display, .. } if display == "42"));
+    assert!(matches!(chain[3], MessageType::MentionAll));
+    assert!(matches!(&chain[4], MessageType::Unknown { tag, .. } if tag == "cq:face"));
+
+    let chain = CqCode.parse("[CQ:image,file=abc.image,url=https://example.com/a.png]");
+    assert!(matches!(
+      &chain[0],
+      MessageType::Image { id, url: Some(url), .. } if id == b"abc.image" && url == "https://example.com/a.png"
+    ));
+    assert_eq!(
+      CqCode.render(&chain, &numeric_id),
+      "[CQ:image,file=https://example.com/a.png]"
+    );
+  }
+}
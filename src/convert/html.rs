@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+
+use arcstr::ArcStr;
+
+use crate::{
+  convert::{into_chain, into_spans, numeric_profile, placeholder, Dialect, Piece, MAX_NESTING},
+  data::{
+    message::{MessageType, Profile},
+    rich::{to_plain_text, Span},
+  },
+};
+
+/// Flavour of HTML spoken by a platform.
+pub struct Html {
+  bold: &'static str,
+  italic: &'static str,
+  underline: &'static str,
+  strikethrough: &'static str,
+  /// Opening and closing tag of spoilers.
+  spoiler: (&'static str, &'static str),
+  /// Links to this prefix followed by a user id are mentions.
+  mention_link: &'static str,
+  /// User ids are numbers, as opposed to Matrix ids like `@user:server`.
+  numeric_ids: bool,
+  /// Line breaks are written as `<br>` outside of code.
+  line_breaks: bool,
+  /// Plain text mentioning everyone.
+  mention_all: Option<&'static str>,
+}
+impl Html {
+  /// `formatted_body` of Matrix messages. Mentions are rendered for profiles
+  /// whose id is the UTF-8 Matrix user id.
+  pub const MATRIX: Html = Html {
+    bold: "strong",
+    italic: "em",
+    underline: "u",
+    strikethrough: "del",
+    spoiler: ("<span data-mx-spoiler>", "</span>"),
+    mention_link: "https://matrix.to/#/",
+    numeric_ids: false,
+    line_breaks: true,
+    mention_all: Some("@room"),
+  };
+  /// Telegram `HTML` parse mode.
+  pub const TELEGRAM: Html = Html {
+    bold: "b",
+    italic: "i",
+    underline: "u",
+    strikethrough: "s",
+    spoiler: ("<tg-spoiler>", "</tg-spoiler>"),
+    mention_link: "tg://user?id=",
+    numeric_ids: true,
+    line_breaks: false,
+    mention_all: None,
+  };
+
+  pub fn escape(&self, text: &str) -> String {
+    let escaped = text
+      .replace('&', "&amp;")
+      .replace('<', "&lt;")
+      .replace('>', "&gt;")
+      .replace('"', "&quot;");
+    if self.line_breaks {
+      escaped.replace('\n', "<br>")
+    } else {
+      escaped
+    }
+  }
+
+  fn escape_code(&self, text: &str) -> String {
+    Html {
+      line_breaks: false,
+      ..*self
+    }
+    .escape(text)
+  }
+
+  fn render_span(&self, span: &Span, text: &mut String) {
+    let wrap = |open: &str, close: &str, children: &[Span], text: &mut String| {
+      text.push_str(open);
+      for child in children {
+        self.render_span(child, text);
+      }
+      text.push_str(close);
+    };
+    match span {
+      Span::Text { content } => text.push_str(&self.escape(content)),
+      Span::Bold { children } => wrap(&open(self.bold), &close(self.bold), children, text),
+      Span::Italic { children } => wrap(&open(self.italic), &close(self.italic), children, text),
+      Span::Underline { children } => wrap(
+        &open(self.underline),
+        &close(self.underline),
+        children,
+        text,
+      ),
+      Span::Strikethrough { children } => wrap(
+        &open(self.strikethrough),
+        &close(self.strikethrough),
+        children,
+        text,
+      ),
+      Span::Spoiler { children } => wrap(self.spoiler.0, self.spoiler.1, children, text),
+      Span::Link { url, children } => wrap(
+        &format!("<a href=\"{}\">", self.escape_code(url)),
+        "</a>",
+        children,
+        text,
+      ),
+      Span::Code { content } => {
+        text.push_str(&format!("<code>{}</code>", self.escape_code(content)));
+      }
+      Span::CodeBlock { language, content } => {
+        match language {
+          Some(language) => text.push_str(&format!(
+            "<pre><code class=\"language-{}\">",
+            self.escape_code(language)
+          )),
+          None => text.push_str("<pre><code>"),
+        }
+        text.push_str(&self.escape_code(content));
+        text.push_str("</code></pre>");
+      }
+    }
+  }
+}
+
+fn open(tag: &str) -> String {
+  format!("<{}>", tag)
+}
+fn close(tag: &str) -> String {
+  format!("</{}>", tag)
+}
+
+impl Dialect for Html {
+  fn parse(&self, text: &str) -> Vec<MessageType> {
+    let mut parser = Parser {
+      html: self,
+      text,
+      pos: 0,
+    };
+    into_chain(parser.parse_children(None, 0))
+  }
+
+  fn render(&self, chain: &[MessageType], mentions: &dyn Fn(&Profile) -> Option<String>) -> String {
+    let mut text = String::new();
+    for segment in chain {
+      match segment {
+        MessageType::Text { content } | MessageType::Edit { content } => {
+          text.push_str(&self.escape(content))
+        }
+        MessageType::Rich { spans } => {
+          for span in spans {
+            self.render_span(span, &mut text);
+          }
+        }
+        MessageType::Mention { profile, display } => match mentions(profile) {
+          Some(id) => text.push_str(&format!(
+            "<a href=\"{}{}\">{}</a>",
+            self.mention_link,
+            self.escape_code(&id),
+            self.escape(display)
+          )),
+          None => text.push_str(&self.escape(&format!("@{}", display))),
+        },
+        MessageType::MentionAll if self.mention_all.is_some() => {
+          text.push_str(self.mention_all.unwrap_or_default())
+        }
+        _ => text.push_str(&self.escape(&placeholder(segment))),
+      }
+    }
+    text
+  }
+}
+
+enum Token {
+  Text(String),
+  Open {
+    name: String,
+    attributes: HashMap<String, String>,
+  },
+  Close(String),
+}
+
+struct Parser<'a> {
+  html: &'a Html,
+  text: &'a str,
+  pos: usize,
+}
+impl<'a> Parser<'a> {
+  fn next_token(&mut self) -> Option<Token> {
+    let rest = &self.text[self.pos..];
+    if rest.is_empty() {
+      return None;
+    }
+    if rest.starts_with('<') {
+      let Some(end) = rest.find('>') else {
+        // no tag can follow either
+        self.pos = self.text.len();
+        return Some(Token::Text(unescape(rest)));
+      };
+      self.pos += end + 1;
+      let tag = rest[1..end].trim().trim_end_matches('/');
+      if let Some(name) = tag.strip_prefix('/') {
+        return Some(Token::Close(name.trim().to_ascii_lowercase()));
+      }
+      let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+      return Some(Token::Open {
+        name: name.to_ascii_lowercase(),
+        attributes: parse_attributes(attributes),
+      });
+    }
+    let end = rest.find('<').unwrap_or(rest.len());
+    self.pos += end;
+    Some(Token::Text(unescape(&rest[..end])))
+  }
+
+  /// Parses up to the closing tag of `parent`, `depth` counts the enclosing
+  /// elements. Block elements stand on lines of their own.
+  fn parse_children(&mut self, parent: Option<&str>, depth: usize) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    // a block ended and its line has to be ended before anything follows
+    let mut after_block = false;
+    loop {
+      let start = self.pos;
+      let Some(token) = self.next_token() else {
+        break;
+      };
+      let mut block = false;
+      let new = match token {
+        // whitespace between blocks is no content
+        Token::Text(text) if after_block && text.trim().is_empty() => continue,
+        Token::Text(text) => match self.html.mention_all {
+          Some(everyone) if depth == 0 => split_mention_all(&text, everyone),
+          _ => vec![Piece::text(text)],
+        },
+        Token::Close(name) if Some(name.as_str()) == parent => break,
+        Token::Close(_) => continue,
+        Token::Open { name, .. } if name == "br" => vec![Piece::text("\n")],
+        // deeper markup is taken as text
+        Token::Open { .. } if depth >= MAX_NESTING => {
+          vec![Piece::text(&self.text[start..self.pos])]
+        }
+        Token::Open { name, attributes } => {
+          let children = self.parse_children(Some(&name), depth + 1);
+          block = BLOCKS.contains(&name.as_str());
+          self.element(&name, &attributes, parent, children)
+        }
+      };
+      if new.is_empty() {
+        continue;
+      }
+      if block || std::mem::take(&mut after_block) {
+        end_line(&mut pieces);
+      }
+      pieces.extend(new);
+      after_block = block;
+    }
+    pieces
+  }
+
+  fn element(
+    &self,
+    name: &str,
+    attributes: &HashMap<String, String>,
+    parent: Option<&str>,
+    children: Vec<Piece>,
+  ) -> Vec<Piece> {
+    let span = match name {
+      "b" | "strong" => Span::Bold {
+        children: into_spans(children),
+      },
+      "i" | "em" => Span::Italic {
+        children: into_spans(children),
+      },
+      "u" | "ins" => Span::Underline {
+        children: into_spans(children),
+      },
+      "s" | "strike" | "del" => Span::Strikethrough {
+        children: into_spans(children),
+      },
+      "tg-spoiler" => Span::Spoiler {
+        children: into_spans(children),
+      },
+      "span"
+        if attributes.contains_key("data-mx-spoiler")
+          || attributes.get("class").map(String::as_str) == Some("tg-spoiler") =>
+      {
+        Span::Spoiler {
+          children: into_spans(children),
+        }
+      }
+      "code" => {
+        let content = to_plain_text(&into_spans(children));
+        match attributes
+          .get("class")
+          .and_then(|class| class.strip_prefix("language-"))
+        {
+          Some(language) if parent == Some("pre") => Span::CodeBlock {
+            language: Some(language.to_owned()),
+            content,
+          },
+          _ => Span::Code { content },
+        }
+      }
+      "pre" => match into_spans(children).as_slice() {
+        [block @ Span::CodeBlock { .. }] => block.clone(),
+        [Span::Code { content }] => Span::CodeBlock {
+          language: None,
+          content: content.clone(),
+        },
+        spans => Span::CodeBlock {
+          language: None,
+          content: to_plain_text(spans),
+        },
+      },
+      "a" => {
+        let href = attributes.get("href").cloned().unwrap_or_default();
+        let children = into_spans(children);
+        if let Some(id) = href.strip_prefix(self.html.mention_link) {
+          let display = to_plain_text(&children);
+          let profile = if self.html.numeric_ids {
+            match id.parse::<i64>() {
+              Ok(id) => Some(numeric_profile(id, Some(display.clone()))),
+              Err(_) => return vec![Piece::text(display)],
+            }
+          } else if id.starts_with('@') && id.contains(':') {
+            Some(Profile {
+              id: id.as_bytes().to_vec(),
+              username: None,
+              nick: Some(display.clone()),
+            })
+          } else {
+            // permalinks to rooms and events
+            None
+          };
+          if let Some(profile) = profile {
+            return vec![Piece::Mention { profile, display }];
+          }
+        }
+        Span::Link {
+          url: ArcStr::from(href),
+          children,
+        }
+      }
+      // fallback of Matrix replies quoting the message replied to
+      "mx-reply" => return Vec::new(),
+      _ => return children,
+    };
+    vec![Piece::Span(span)]
+  }
+}
+
+/// Elements whose content starts on a new line and ends its line.
+const BLOCKS: &[&str] = &["p", "div", "blockquote", "ul", "ol", "li"];
+
+fn end_line(pieces: &mut Vec<Piece>) {
+  match pieces.last() {
+    None => {}
+    Some(Piece::Span(Span::Text { content })) if content.ends_with('\n') => {}
+    Some(_) => pieces.push(Piece::text("\n")),
+  }
+}
+
+/// Splits out mentions of everyone, which have to stand as a word of their
+/// own.
+fn split_mention_all(text: &str, everyone: &str) -> Vec<Piece> {
+  let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+  let mut pieces = Vec::new();
+  let mut rest = text;
+  let mut from = 0;
+  while let Some(found) = rest[from..].find(everyone) {
+    let at = from + found;
+    let end = at + everyone.len();
+    if is_word(rest[..at].chars().next_back()) || is_word(rest[end..].chars().next()) {
+      from = end;
+      continue;
+    }
+    if at > 0 {
+      pieces.push(Piece::text(&rest[..at]));
+    }
+    pieces.push(Piece::MentionAll);
+    rest = &rest[end..];
+    from = 0;
+  }
+  if !rest.is_empty() {
+    pieces.push(Piece::text(rest));
+  }
+  pieces
+}
+
+fn parse_attributes(text: &str) -> HashMap<String, String> {
+  let mut attributes = HashMap::new();
+  let mut rest = text.trim();
+  while !rest.is_empty() {
+    let name_end = rest
+      .find(|c: char| c == '=' || c.is_whitespace())
+      .unwrap_or(rest.len());
+    let name = rest[..name_end].to_ascii_lowercase();
+    rest = rest[name_end..].trim_start();
+    let value = match rest.strip_prefix('=') {
+      Some(value) => {
+        let value = value.trim_start();
+        let (value, remaining) = match value.chars().next() {
+          Some(quote @ ('"' | '\'')) => {
+            let end = value[1..].find(quote).map_or(value.len(), |end| end + 1);
+            (&value[1..end], value.get(end + 1..).unwrap_or_default())
+          }
+          _ => value.split_at(value.find(char::is_whitespace).unwrap_or(value.len())),
+        };
+        rest = remaining.trim_start();
+        unescape(value)
+      }
+      None => String::new(),
+    };
+    if !name.is_empty() {
+      attributes.insert(name, value);
+    }
+  }
+  attributes
+}
+
+fn unescape(text: &str) -> String {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    unescaped.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let entity = rest
+      .bytes()
+      .take(11)
+      .position(|b| b == b';')
+      .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+    match entity {
+      Some((c, end)) => {
+        unescaped.push(c);
+        rest = &rest[end + 1..];
+      }
+      None => {
+        unescaped.push('&');
+        rest = &rest[1..];
+      }
+    }
+  }
+  unescaped.push_str(rest);
+  unescaped
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+  match entity {
+    "amp" => Some('&'),
+    "lt" => Some('<'),
+    "gt" => Some('>'),
+    "quot" => Some('"'),
+    "apos" => Some('\''),
+    "nbsp" => Some('\u{a0}'),
+    _ => {
+      let code = entity.strip_prefix('#')?;
+      let code = match code.strip_prefix(['x', 'X']) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => code.parse().ok()?,
+      };
+      char::from_u32(code)
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::{Duration, Instant};
+
+  use crate::{
+    convert::{html::Html, numeric_id, Dialect, MAX_NESTING},
+    data::{message::MessageType, rich::Span},
+  };
+  #[test]
+  fn test_telegram() {
+    let html = Html::TELEGRAM;
+    let text = "<b>bold <i>italic</i></b> &lt;3 &amp; <u>under</u> <s>strike</s> \
+                <tg-spoiler>spoiler</tg-spoiler> <a href=\"https://example.com/?a=1&amp;b=2\">link</a> \
+                <code>x &lt; y</code> <a href=\"tg://user?id=42\">Name</a>\n\
+                <pre><code class=\"language-rust\">fn main() {}</code></pre>";
+    let chain = html.parse(text);
+    assert_eq!(html.render(&chain, &numeric_id), text);
+    let MessageType::Rich { spans } = &chain[0] else {
+      panic!("not rich text: {:?}", chain);
+    };
+    assert_eq!(spans[1], Span::text(" <3 & "));
+    assert!(spans.contains(&Span::Link {
+      url: "https://example.com/?a=1&b=2".into(),
+      children: vec![Span::text("link")],
+    }));
+    assert!(matches!(&chain[1], MessageType::Mention { display, .. } if display == "Name"));
+
+    // other spellings are understood too
+    let chain = html.parse("<strong>a</strong><br/><span class=\"tg-spoiler\">b</span>");
+    assert_eq!(
+      html.render(&chain, &numeric_id),
+      "<b>a</b>\n<tg-spoiler>b</tg-spoiler>"
+    );
+
+    let text = "你好 <b>世界</b> <i>é</i>< 3";
+    let chain = html.parse(text);
+    assert_eq!(
+      html.render(&chain, &numeric_id),
+      "你好 <b>世界</b> <i>é</i>&lt; 3"
+    );
+  }
+
+  #[test]
+  fn test_matrix() {
+    let html = Html::MATRIX;
+    let text = "<strong>bold</strong><br><em>italic</em> <del>gone</del> \
+                <span data-mx-spoiler>spoiler</span> @room \
+                <a href=\"https://matrix.to/#/@alice:example.org\">Alice</a> \
+                <pre><code>line 1\nline 2</code></pre>";
+    let chain = html.parse(text);
+    let mentions =
+      |profile: &crate::data::message::Profile| String::from_utf8(profile.id.clone()).ok();
+    assert_eq!(html.render(&chain, &mentions), text);
+    assert!(matches!(chain[1], MessageType::MentionAll));
+    assert!(
+      matches!(&chain[3], MessageType::Mention { profile, .. } if profile.id == b"@alice:example.org")
+    );
+    let MessageType::Rich { spans } = &chain[0] else {
+      panic!("not rich text: {:?}", chain);
+    };
+    assert_eq!(spans[1], Span::text("\n"));
+    assert!(
+      matches!(&chain[4], MessageType::Rich { spans } if spans[1] == Span::CodeBlock { language: None, content: "line 1\nline 2".into() })
+    );
+
+    let text = "你好<br><strong>世界</strong> @room 好";
+    let chain = html.parse(text);
+    assert_eq!(html.render(&chain, &mentions), text);
+
+    // the quote in replies is left out, blocks end their lines
+    let text = "<mx-reply><blockquote>\
+                <a href=\"https://matrix.to/#/!room:example.org/$event\">In reply to</a> \
+                <a href=\"https://matrix.to/#/@bob:example.org\">@bob</a><br>quoted\
+                </blockquote></mx-reply><p>one</p><p>two</p>\n<ul><li>a</li><li>b</li></ul> @roommate";
+    let chain = html.parse(text);
+    assert!(
+      matches!(chain.as_slice(), [MessageType::Text { content }] if content == "one\ntwo\na\nb\n @roommate")
+    );
+    // only users are mentioned, other permalinks are links
+    let chain = html.parse("<a href=\"https://matrix.to/#/!room:example.org\">room</a>");
+    assert!(
+      matches!(&chain[0], MessageType::Rich { spans } if matches!(&spans[0], Span::Link { url, .. } if url == "https://matrix.to/#/!room:example.org"))
+    );
+  }
+
+  #[test]
+  fn test_unmatched() {
+    let start = Instant::now();
+    let html = Html::MATRIX;
+    for unit in ["<", "&", "&#", "< b"] {
+      let text = unit.repeat(20000);
+      let chain = html.parse(&text);
+      assert!(matches!(chain.as_slice(), [MessageType::Text { content }] if *content == text));
+    }
+    // nesting is capped instead of overflowing the stack
+    let chain = html.parse(&format!("{}x", "<b>".repeat(50000)));
+    let rendered = html.render(&chain, &|_| None);
+    assert!(rendered.contains("&lt;b&gt;x</strong>"));
+    assert_eq!(rendered.matches("</strong>").count(), MAX_NESTING);
+    assert!(start.elapsed() < Duration::from_secs(5));
+  }
+}
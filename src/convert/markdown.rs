@@ -0,0 +1,682 @@
+use std::collections::HashMap;
+
+use arcstr::ArcStr;
+
+use crate::{
+  convert::{into_chain, into_spans, numeric_profile, placeholder, Dialect, Piece, MAX_NESTING},
+  data::{
+    message::{MessageType, Profile},
+    rich::{to_plain_text, Span},
+  },
+  render::{escape_markdown, markdown_code, markdown_code_block, markdown_url, wrap_markdown},
+};
+
+/// Flavour of Markdown spoken by a platform.
+pub struct Markdown {
+  bold: &'static str,
+  /// The first one is used when rendering.
+  italic: &'static [&'static str],
+  underline: &'static str,
+  strikethrough: &'static str,
+  spoiler: &'static str,
+  /// Characters escaped with a backslash in text.
+  special: &'static str,
+  /// Backslash escapes are honoured inside code.
+  code_escapes: bool,
+  /// Delimiters count as in CommonMark, only with non-whitespace on their
+  /// inner side and `_` not inside words. Delimiters in bare urls are text.
+  flanking: bool,
+  /// Links to this prefix followed by a numeric id are mentions.
+  mention_link: Option<&'static str>,
+  /// `<@id>` mentions and `@everyone`.
+  discord_mentions: bool,
+}
+impl Markdown {
+  pub const DISCORD: Markdown = Markdown {
+    bold: "**",
+    italic: &["*", "_"],
+    underline: "__",
+    strikethrough: "~~",
+    spoiler: "||",
+    special: "\\*_~`|<>[]()@",
+    code_escapes: false,
+    flanking: true,
+    mention_link: None,
+    discord_mentions: true,
+  };
+  /// Telegram `MarkdownV2`.
+  pub const TELEGRAM_V2: Markdown = Markdown {
+    bold: "*",
+    italic: &["_"],
+    underline: "__",
+    strikethrough: "~",
+    spoiler: "||",
+    special: "\\_*[]()~`>#+-=|{}.!",
+    code_escapes: true,
+    // every special character not meant as markup is escaped
+    flanking: false,
+    mention_link: Some("tg://user?id="),
+    discord_mentions: false,
+  };
+
+  /// Delimiters of styles, longest first so that `__` is not taken for two
+  /// `_`.
+  fn delimiters(&self) -> Vec<(&'static str, Style)> {
+    let mut delimiters = vec![
+      (self.bold, Style::Bold),
+      (self.underline, Style::Underline),
+      (self.strikethrough, Style::Strikethrough),
+      (self.spoiler, Style::Spoiler),
+    ];
+    delimiters.extend(self.italic.iter().map(|italic| (*italic, Style::Italic)));
+    delimiters.sort_by_key(|(delimiter, _)| std::cmp::Reverse(delimiter.len()));
+    delimiters
+  }
+
+  pub fn escape(&self, text: &str) -> String {
+    escape_markdown(text, self.special)
+  }
+
+  fn render_spans(&self, spans: &[Span]) -> String {
+    spans.iter().map(|span| self.render_span(span)).collect()
+  }
+
+  fn render_span(&self, span: &Span) -> String {
+    let children = || self.render_spans(span.children());
+    match span {
+      Span::Text { content } => self.escape(content),
+      Span::Bold { .. } => wrap_markdown(self.bold, &children()),
+      Span::Italic { .. } => wrap_markdown(self.italic[0], &children()),
+      Span::Underline { .. } => wrap_markdown(self.underline, &children()),
+      Span::Strikethrough { .. } => wrap_markdown(self.strikethrough, &children()),
+      Span::Spoiler { .. } => wrap_markdown(self.spoiler, &children()),
+      Span::Link { url, .. } => format!("[{}]({})", children(), markdown_url(url)),
+      Span::Code { content } if self.code_escapes => format!("`{}`", escape_code(content)),
+      Span::Code { content } => markdown_code(content),
+      Span::CodeBlock { language, content } if self.code_escapes => format!(
+        "```{}\n{}\n```",
+        language.as_deref().unwrap_or_default(),
+        escape_code(content)
+      ),
+      Span::CodeBlock { language, content } => markdown_code_block(language.as_deref(), content),
+    }
+  }
+}
+
+impl Dialect for Markdown {
+  fn parse(&self, text: &str) -> Vec<MessageType> {
+    into_chain(Parser::new(self, text).parse())
+  }
+
+  fn render(&self, chain: &[MessageType], mentions: &dyn Fn(&Profile) -> Option<String>) -> String {
+    let mut text = String::new();
+    for segment in chain {
+      match segment {
+        MessageType::Text { content } | MessageType::Edit { content } => {
+          text.push_str(&self.escape(content))
+        }
+        MessageType::Rich { spans } => text.push_str(&self.render_spans(spans)),
+        MessageType::Mention { profile, display } => match (mentions(profile), self.mention_link) {
+          (Some(id), _) if self.discord_mentions => text.push_str(&format!("<@{}>", id)),
+          (Some(id), Some(link)) => {
+            text.push_str(&format!("[{}]({}{})", self.escape(display), link, id))
+          }
+          _ => text.push_str(&self.escape(&format!("@{}", display))),
+        },
+        MessageType::MentionAll if self.discord_mentions => text.push_str("@everyone"),
+        _ => text.push_str(&self.escape(&placeholder(segment))),
+      }
+    }
+    text
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+  Bold,
+  Italic,
+  Underline,
+  Strikethrough,
+  Spoiler,
+}
+impl Style {
+  fn span(self, children: Vec<Span>) -> Span {
+    match self {
+      Style::Bold => Span::Bold { children },
+      Style::Italic => Span::Italic { children },
+      Style::Underline => Span::Underline { children },
+      Style::Strikethrough => Span::Strikethrough { children },
+      Style::Spoiler => Span::Spoiler { children },
+    }
+  }
+}
+
+fn escape_code(content: &str) -> String {
+  content.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+/// Delimiter or `[` still waiting to be closed.
+struct Opener {
+  delimiter: &'static str,
+  /// `None` for the `[` of a link.
+  style: Option<Style>,
+  /// Index of the delimiter in the parsed pieces, where it stays as text when
+  /// it is never closed.
+  at: usize,
+}
+
+/// Single pass over the text. Delimiters are kept as text until their closing
+/// counterpart turns them into a span, so unmatched ones cost nothing extra.
+struct Parser<'a> {
+  markdown: &'a Markdown,
+  delimiters: Vec<(&'static str, Style)>,
+  text: &'a str,
+  pos: usize,
+  pieces: Vec<Piece>,
+  /// Text not pushed to `pieces` yet.
+  pending: String,
+  openers: Vec<Opener>,
+  /// Closing markers missing from the text after the given position.
+  unclosed: HashMap<&'a str, usize>,
+  /// End of the bare url `pos` is in.
+  url_end: usize,
+}
+impl<'a> Parser<'a> {
+  fn new(markdown: &'a Markdown, text: &'a str) -> Self {
+    Self {
+      markdown,
+      delimiters: markdown.delimiters(),
+      text,
+      pos: 0,
+      pieces: Vec::new(),
+      pending: String::new(),
+      openers: Vec::new(),
+      unclosed: HashMap::new(),
+      url_end: 0,
+    }
+  }
+
+  fn rest(&self) -> &'a str {
+    &self.text[self.pos..]
+  }
+
+  fn before(&self) -> Option<char> {
+    self.text[..self.pos].chars().next_back()
+  }
+
+  fn parse(mut self) -> Vec<Piece> {
+    while let Some(c) = self.rest().chars().next() {
+      if self.markdown.flanking && self.pos >= self.url_end {
+        if let Some(end) = self.bare_url() {
+          self.url_end = end;
+        }
+      }
+      if self.close_style() {
+        continue;
+      }
+      if c == '\\' {
+        if let Some(escaped) = self.rest()[1..]
+          .chars()
+          .next()
+          .filter(char::is_ascii_punctuation)
+        {
+          self.pos += 1 + escaped.len_utf8();
+          self.pending.push(escaped);
+          continue;
+        }
+      }
+      let start = self.pos;
+      if let Some(piece) = self.special() {
+        self.push(piece);
+        continue;
+      }
+      self.pos = start;
+      if c == '`' {
+        // a fence that is never closed is text as a whole
+        let fence = self.fence();
+        self.pos += fence.len();
+        self.pending.push_str(fence);
+        continue;
+      }
+      if c == ']' && self.close_link() {
+        continue;
+      }
+      self.pos = start;
+      let opener = match c {
+        '[' => Some(("[", None)),
+        _ if self.pos < self.url_end => None,
+        _ => self
+          .delimiters
+          .iter()
+          .find(|(delimiter, _)| self.rest().starts_with(delimiter))
+          .filter(|(delimiter, _)| self.can_open(delimiter))
+          .map(|(delimiter, style)| (*delimiter, Some(*style))),
+      };
+      match opener {
+        Some((delimiter, style)) => {
+          self.pos += delimiter.len();
+          self.flush();
+          // deeper markup is taken as text
+          if self.openers.len() < MAX_NESTING {
+            self.openers.push(Opener {
+              delimiter,
+              style,
+              at: self.pieces.len(),
+            });
+          }
+          self.pieces.push(Piece::text(delimiter));
+        }
+        None => {
+          self.pos += c.len_utf8();
+          self.pending.push(c);
+        }
+      }
+    }
+    self.flush();
+    self.pieces
+  }
+
+  fn flush(&mut self) {
+    if !self.pending.is_empty() {
+      let pending = std::mem::take(&mut self.pending);
+      self.pieces.push(Piece::text(pending));
+    }
+  }
+
+  fn push(&mut self, piece: Piece) {
+    self.flush();
+    self.pieces.push(piece);
+  }
+
+  /// Takes the pieces after the opener at `index`, dropping the opener and
+  /// leaving the ones opened after it as text.
+  fn close(&mut self, index: usize) -> Vec<Piece> {
+    self.flush();
+    let at = self.openers[index].at;
+    self.openers.truncate(index);
+    let children = self.pieces.split_off(at + 1);
+    self.pieces.pop();
+    children
+  }
+
+  /// Whether `delimiter`, which comes next, may open a style.
+  fn can_open(&self, delimiter: &str) -> bool {
+    if !self.markdown.flanking {
+      return true;
+    }
+    let after = self.rest()[delimiter.len()..].chars().next();
+    let in_word = delimiter.starts_with('_') && self.before().is_some_and(char::is_alphanumeric);
+    after.is_some_and(|c| !c.is_whitespace()) && !in_word
+  }
+
+  /// Whether `delimiter`, which comes next, may close a style.
+  fn can_close(&self, delimiter: &str) -> bool {
+    if !self.markdown.flanking {
+      return true;
+    }
+    let after = self.rest()[delimiter.len()..].chars().next();
+    let in_word = delimiter.starts_with('_') && after.is_some_and(char::is_alphanumeric);
+    self.before().is_some_and(|c| !c.is_whitespace()) && !in_word
+  }
+
+  /// End of the bare url starting at `pos`. Like autolinks of GitHub Flavored
+  /// Markdown it leaves out trailing punctuation.
+  fn bare_url(&self) -> Option<usize> {
+    let rest = self.rest();
+    if !(rest.starts_with("http://") || rest.starts_with("https://"))
+      || self.before().is_some_and(char::is_alphanumeric)
+    {
+      return None;
+    }
+    let end = rest
+      .find(|c: char| c.is_whitespace() || "<>[]".contains(c))
+      .unwrap_or(rest.len());
+    let url = rest[..end].trim_end_matches(|c: char| "?!.,:;*_~|)".contains(c));
+    Some(self.pos + url.len())
+  }
+
+  /// Closes the innermost open style whose delimiter comes next, unless that
+  /// would leave it empty and it stays text.
+  fn close_style(&mut self) -> bool {
+    if self.pos < self.url_end {
+      return false;
+    }
+    let rest = self.rest();
+    let Some(index) = self
+      .openers
+      .iter()
+      .rposition(|opener| opener.style.is_some() && rest.starts_with(opener.delimiter))
+    else {
+      return false;
+    };
+    let Opener {
+      delimiter, style, ..
+    } = self.openers[index];
+    let Some(style) = style else {
+      return false;
+    };
+    if !self.can_close(delimiter) {
+      return false;
+    }
+    self.flush();
+    if self.pieces.len() == self.openers[index].at + 1 {
+      // an empty opener is the innermost one, the delimiter takes its place
+      self.openers.pop();
+      return false;
+    }
+    self.pos += delimiter.len();
+    let children = self.close(index);
+    self
+      .pieces
+      .push(Piece::Span(style.span(into_spans(children))));
+    true
+  }
+
+  /// Closes the innermost `[` when `](url)` follows.
+  fn close_link(&mut self) -> bool {
+    let Some(index) = self
+      .openers
+      .iter()
+      .rposition(|opener| opener.style.is_none())
+    else {
+      return false;
+    };
+    if !self.rest().starts_with("](") {
+      return false;
+    }
+    self.pos += 2;
+    let Some(url) = self.read_until(")", true) else {
+      return false;
+    };
+    let children = self.close(index);
+    let piece = match self
+      .markdown
+      .mention_link
+      .and_then(|link| url.strip_prefix(link))
+      .and_then(|id| id.parse::<i64>().ok())
+    {
+      Some(id) => {
+        let display = to_plain_text(&into_spans(children));
+        Piece::Mention {
+          profile: numeric_profile(id, Some(display.clone())),
+          display,
+        }
+      }
+      None => Piece::Span(Span::Link {
+        url: ArcStr::from(url),
+        children: into_spans(children),
+      }),
+    };
+    self.pieces.push(piece);
+    true
+  }
+
+  /// Code and mentions, leaving `pos` anywhere when there is none.
+  fn special(&mut self) -> Option<Piece> {
+    let rest = self.rest();
+    if rest.starts_with('`') {
+      let fence = self.fence();
+      self.pos += fence.len();
+      return match fence.len() {
+        1 | 2 => self.code(fence),
+        _ => self.code_block(fence),
+      };
+    }
+    if self.markdown.discord_mentions {
+      for everyone in ["@everyone", "@here"] {
+        if rest.starts_with(everyone) {
+          self.pos += everyone.len();
+          return Some(Piece::MentionAll);
+        }
+      }
+      if let Some(mention) = rest.strip_prefix("<@") {
+        let mention = mention.strip_prefix('!').unwrap_or(mention);
+        let end = mention.bytes().take_while(u8::is_ascii_digit).count();
+        if !mention[end..].starts_with('>') {
+          return None;
+        }
+        let id = mention[..end].parse::<i64>().ok()?;
+        self.pos = self.text.len() - mention.len() + end + 1;
+        return Some(Piece::Mention {
+          profile: numeric_profile(id, None),
+          display: id.to_string(),
+        });
+      }
+    }
+    None
+  }
+
+  /// Reads up to the unescaped `end`, resolving escapes when `escapes`. The
+  /// rest of the text is searched for a missing `end` only once.
+  fn read_until(&mut self, end: &'a str, escapes: bool) -> Option<String> {
+    if matches!(self.unclosed.get(end), Some(from) if *from <= self.pos) {
+      return None;
+    }
+    let start = self.pos;
+    let mut content = String::new();
+    loop {
+      let rest = self.rest();
+      if rest.starts_with(end) {
+        self.pos += end.len();
+        return Some(content);
+      }
+      let mut chars = rest.chars();
+      let Some(c) = chars.next() else {
+        self.unclosed.insert(end, start);
+        return None;
+      };
+      if escapes && c == '\\' {
+        if let Some(escaped) = chars.next() {
+          content.push(escaped);
+          self.pos += 1 + escaped.len_utf8();
+          continue;
+        }
+      }
+      content.push(c);
+      self.pos += c.len_utf8();
+    }
+  }
+
+  /// Run of backticks at `pos`. With escapes in code backticks in it are
+  /// escaped, so fences have a fixed length.
+  fn fence(&self) -> &'a str {
+    let rest = self.rest();
+    let run = rest.len() - rest.trim_start_matches('`').len();
+    match (self.markdown.code_escapes, run) {
+      (true, 1 | 2) => &rest[..1],
+      (true, _) => &rest[..3],
+      _ => &rest[..run],
+    }
+  }
+
+  fn code(&mut self, fence: &'a str) -> Option<Piece> {
+    if self.markdown.code_escapes {
+      let content = self.read_until(fence, true)?;
+      return Some(Piece::Span(Span::Code { content }));
+    }
+    // only a run of backticks as long as the opening one closes the span
+    let start = self.pos;
+    let mut content = String::new();
+    loop {
+      let Some(read) = self.read_until(fence, false) else {
+        self.unclosed.insert(fence, start);
+        return None;
+      };
+      content.push_str(&read);
+      let longer = self.rest().len() - self.rest().trim_start_matches('`').len();
+      if longer == 0 {
+        break;
+      }
+      content.push_str(fence);
+      content.push_str(&self.rest()[..longer]);
+      self.pos += longer;
+    }
+    Some(Piece::Span(Span::Code {
+      content: strip_padding(content),
+    }))
+  }
+
+  /// Fences of three or more backticks, a block when the language line ends
+  /// before the closing one.
+  fn code_block(&mut self, fence: &'a str) -> Option<Piece> {
+    let escapes = self.markdown.code_escapes;
+    let content = self.read_until(fence, escapes)?;
+    let Some((language, content)) = content.split_once('\n') else {
+      let span = if escapes {
+        Span::CodeBlock {
+          language: None,
+          content,
+        }
+      } else {
+        Span::Code {
+          content: strip_padding(content),
+        }
+      };
+      return Some(Piece::Span(span));
+    };
+    let language = language.trim();
+    let language = (!language.is_empty()).then(|| language.to_owned());
+    let content = content.strip_suffix('\n').unwrap_or(content).to_owned();
+    Some(Piece::Span(Span::CodeBlock { language, content }))
+  }
+}
+
+/// Drops the space on each side of inline code that keeps the content off
+/// the fence.
+fn strip_padding(content: String) -> String {
+  if content.starts_with(' ') && content.ends_with(' ') && !content.trim_matches(' ').is_empty() {
+    content[1..content.len() - 1].to_owned()
+  } else {
+    content
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::{Duration, Instant};
+
+  use crate::{
+    convert::{markdown::Markdown, numeric_id, numeric_profile, Dialect},
+    data::{message::MessageType, rich::Span},
+  };
+  #[test]
+  fn test_telegram() {
+    let markdown = Markdown::TELEGRAM_V2;
+    let text = "*bold _italic_* 1\\.5\\! [link](https://example.com/(a\\)) `co\\`de` ||spoiler|| \
+                ~strike~ __under__ [Name](tg://user?id=42) ```rust\nfn main() {}\n```";
+    let chain = markdown.parse(text);
+    assert_eq!(markdown.render(&chain, &numeric_id), text);
+    let MessageType::Rich { spans } = &chain[0] else {
+      panic!("not rich text: {:?}", chain);
+    };
+    assert_eq!(
+      spans[0],
+      Span::Bold {
+        children: vec![
+          Span::text("bold "),
+          Span::Italic {
+            children: vec![Span::text("italic")]
+          }
+        ]
+      }
+    );
+    assert_eq!(
+      spans[2],
+      Span::Link {
+        url: "https://example.com/(a)".into(),
+        children: vec![Span::text("link")],
+      }
+    );
+    assert!(matches!(&chain[1], MessageType::Mention { display, .. } if display == "Name"));
+    assert!(
+      matches!(&chain[2], MessageType::Rich { spans } if matches!(&spans[1], Span::CodeBlock { language: Some(language), .. } if language == "rust"))
+    );
+
+    // unmatched delimiters stay text
+    let chain = markdown.parse("2 * 3");
+    assert!(matches!(chain.as_slice(), [MessageType::Text { content }] if content == "2 * 3"));
+    assert_eq!(markdown.render(&chain, &numeric_id), "2 \\* 3");
+  }
+
+  #[test]
+  fn test_discord() {
+    let markdown = Markdown::DISCORD;
+    let text =
+      "**bold** *italic* __under__ ~~strike~~ ||spoiler|| ``a`b`` \\*not\\* <@42> @everyone";
+    let chain = markdown.parse(text);
+    assert_eq!(markdown.render(&chain, &numeric_id), text);
+    assert!(matches!(
+      &chain[1],
+      MessageType::Mention { profile, display } if *profile == numeric_profile(42, None) && display == "42"
+    ));
+    assert!(matches!(chain[3], MessageType::MentionAll));
+    let MessageType::Rich { spans } = &chain[0] else {
+      panic!("not rich text: {:?}", chain);
+    };
+    assert!(spans.contains(&Span::Code {
+      content: "a`b".into()
+    }));
+    assert!(spans.contains(&Span::text(" *not* ")));
+    // mentions of users without a discord account are written out
+    assert_eq!(markdown.render(&chain[1..2], &|_| None), "\\@42");
+
+    // delimiters inside words, urls and next to spaces are text
+    for text in [
+      "snake_case_name",
+      "https://example.com/foo_bar_baz",
+      "a * b * c",
+    ] {
+      let chain = markdown.parse(text);
+      assert!(matches!(chain.as_slice(), [MessageType::Text { content }] if content == text));
+    }
+    let chain = markdown.parse("*a **b** c*");
+    assert!(
+      matches!(chain.as_slice(), [MessageType::Rich { spans }] if spans[..] == [Span::Italic {
+        children: vec![
+          Span::text("a "),
+          Span::Bold {
+            children: vec![Span::text("b")]
+          },
+          Span::text(" c"),
+        ]
+      }])
+    );
+
+    let spans = vec![
+      Span::Code {
+        content: "a``b".into(),
+      },
+      Span::Italic {
+        children: vec![Span::text(" x ")],
+      },
+    ];
+    let text = markdown.render(
+      &[MessageType::Rich {
+        spans: spans.clone(),
+      }],
+      &numeric_id,
+    );
+    assert_eq!(text, "```a``b``` *x* ");
+    let chain = markdown.parse(&text);
+    assert!(matches!(&chain[0], MessageType::Rich { spans: parsed } if parsed[0] == spans[0]));
+  }
+
+  #[test]
+  fn test_unmatched() {
+    let start = Instant::now();
+    for markdown in [Markdown::TELEGRAM_V2, Markdown::DISCORD] {
+      for unit in ["[", "*", "_", "||", "[a](", "<@1"] {
+        let text = unit.repeat(5000);
+        let chain = markdown.parse(&text);
+        assert!(matches!(chain.as_slice(), [MessageType::Text { content }] if *content == text));
+      }
+      for unit in ["`", "```", "*_~", "[*"] {
+        markdown.parse(&unit.repeat(5000));
+      }
+      // nesting is capped
+      let text = format!("{}x{}", "*_".repeat(5000), "_*".repeat(5000));
+      markdown.parse(&text);
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+  }
+}
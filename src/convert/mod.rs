@@ -0,0 +1,118 @@
+//! Conversion between the markup of chat platforms and [`MessageType`]
+//! chains.
+
+pub mod cq;
+pub mod html;
+pub mod markdown;
+
+use crate::{
+  data::{
+    message::{MessageType, Profile},
+    rich::Span,
+  },
+  render::{Format, Renderer},
+};
+
+/// Deepest formatting the parsers build, deeper markup is taken as text.
+const MAX_NESTING: usize = 32;
+
+/// Markup of one platform.
+pub trait Dialect {
+  fn parse(&self, text: &str) -> Vec<MessageType>;
+
+  /// Renders `chain`. A mention becomes a native one when `mentions` maps its
+  /// profile to a user id on this platform and `@display` otherwise, segments
+  /// the markup cannot carry are replaced by placeholders.
+  fn render(&self, chain: &[MessageType], mentions: &dyn Fn(&Profile) -> Option<String>) -> String;
+}
+
+/// Profile of a user on a platform with numeric ids, which bridges store as
+/// big endian `i64`.
+pub fn numeric_profile(id: i64, nick: Option<String>) -> Profile {
+  Profile {
+    id: id.to_be_bytes().to_vec(),
+    username: None,
+    nick,
+  }
+}
+
+/// Reverses [`numeric_profile`], for rendering mentions of users of the same
+/// platform.
+pub fn numeric_id(profile: &Profile) -> Option<String> {
+  let id: [u8; 8] = profile.id.as_slice().try_into().ok()?;
+  Some(i64::from_be_bytes(id).to_string())
+}
+
+/// Parsed piece of markup, mentions can only stand outside of formatting.
+enum Piece {
+  Span(Span),
+  Mention { profile: Profile, display: String },
+  MentionAll,
+}
+impl Piece {
+  fn text<S: Into<String>>(text: S) -> Self {
+    Piece::Span(Span::text(text))
+  }
+}
+
+/// Turns nested pieces into the children of a span, writing mentions out.
+fn into_spans(pieces: Vec<Piece>) -> Vec<Span> {
+  let mut spans = Vec::new();
+  for piece in pieces {
+    let span = match piece {
+      Piece::Span(span) => span,
+      Piece::Mention { display, .. } => Span::text(format!("@{}", display)),
+      Piece::MentionAll => Span::text("@all"),
+    };
+    push_span(&mut spans, span);
+  }
+  spans
+}
+
+/// Appends `span`, merging adjacent text.
+fn push_span(spans: &mut Vec<Span>, span: Span) {
+  if let (Some(Span::Text { content }), Span::Text { content: more }) = (spans.last_mut(), &span) {
+    content.push_str(more);
+    return;
+  }
+  spans.push(span);
+}
+
+/// Groups parsed pieces into segments, runs of unformatted text become
+/// [`MessageType::Text`] and formatted ones [`MessageType::Rich`].
+fn into_chain(pieces: Vec<Piece>) -> Vec<MessageType> {
+  let mut chain = Vec::new();
+  let mut spans = Vec::new();
+  for piece in pieces {
+    let segment = match piece {
+      Piece::Span(span) => {
+        push_span(&mut spans, span);
+        continue;
+      }
+      Piece::Mention { profile, display } => MessageType::Mention { profile, display },
+      Piece::MentionAll => MessageType::MentionAll,
+    };
+    flush_spans(&mut chain, &mut spans);
+    chain.push(segment);
+  }
+  flush_spans(&mut chain, &mut spans);
+  chain
+}
+
+fn flush_spans(chain: &mut Vec<MessageType>, spans: &mut Vec<Span>) {
+  match spans.as_slice() {
+    [] => {}
+    [Span::Text { content }] => chain.push(MessageType::Text {
+      content: content.clone(),
+    }),
+    _ => chain.push(MessageType::Rich {
+      spans: spans.clone(),
+    }),
+  }
+  spans.clear();
+}
+
+/// Text standing in for a segment a dialect has no markup for.
+fn placeholder(segment: &MessageType) -> String {
+  Renderer::new(Format::Plain).render_chain(std::slice::from_ref(segment))
+}
@@ -1,13 +1,42 @@
 use arcstr::ArcStr;
 use educe::Educe;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Educe)]
+use crate::{
+  data::{
+    decode_tagged,
+    message::{Emoji, Profile},
+  },
+  fl,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+  Online,
+  Idle,
+  DoNotDisturb,
+  Offline,
+}
+
+#[derive(Serialize, Deserialize, Educe, Clone)]
 #[educe(Debug)]
 #[serde(rename_all = "snake_case")]
-#[serde(tag = "t")]
+#[serde(tag = "t", remote = "Self")]
 #[non_exhaustive]
 pub enum Event {
+  /// Asks the bridge that sent an attachment without a url for one.
+  RequestResource {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+  },
+  RespondResource {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    url: ArcStr,
+  },
+  /// [`Event::RequestResource`] as sent by bridges predating it, to be
+  /// answered with [`Event::RespondImage`].
   RequestImage {
     #[serde(with = "serde_bytes")]
     // #[educe(Debug(method = "fmt_bytes"))]
@@ -19,6 +48,64 @@ pub enum Event {
     id: Vec<u8>,
     url: ArcStr,
   },
+  /// A message was deleted or recalled where it was sent. `id` is the
+  /// [`Message::id`](crate::data::message::Message::id) it was bridged with,
+  /// receivers find their copy with
+  /// [`Db::get_msg_id`](crate::db::Db::get_msg_id).
+  DeleteMessage {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    /// Who removed it, when that was not the sender.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    operator: Option<Profile>,
+  },
+  /// `profile` reacted to the message `id` with `emoji`.
+  AddReaction {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    profile: Profile,
+    emoji: Emoji,
+  },
+  RemoveReaction {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    profile: Profile,
+    emoji: Emoji,
+  },
+  /// `profile` started or stopped typing. Like [`Event::Presence`] it is
+  /// not meant to be stored, send it through
+  /// [`MesagistoClient::send_ephemeral`](crate::client::MesagistoClient::send_ephemeral).
+  Typing {
+    profile: Profile,
+    typing: bool,
+  },
+  Presence {
+    profile: Profile,
+    status: Presence,
+  },
+  MemberJoined {
+    profile: Profile,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    inviter: Option<Profile>,
+  },
+  MemberLeft {
+    profile: Profile,
+  },
+  MemberKicked {
+    profile: Profile,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    operator: Option<Profile>,
+  },
+  /// Nickname or avatar of a member changed, `profile` holds the new names
+  /// and `previous` the old ones.
+  MemberUpdated {
+    profile: Profile,
+    previous: Profile,
+    /// Resource id of the new avatar, fetched through
+    /// [`Res::file`](crate::res::Res::file).
+    #[serde(with = "serde_bytes", skip_serializing_if = "Option::is_none", default)]
+    avatar: Option<Vec<u8>>,
+  },
   RequestEcho {
     // should contains group_id, group_name
     name: ArcStr,
@@ -27,11 +114,135 @@ pub enum Event {
     // should contains group_id, group_name
     name: ArcStr,
   },
+  /// Event added by a newer client, kept as it was received.
+  #[serde(skip)]
+  Unknown {
+    tag: String,
+    raw: ciborium::Value,
+  },
+}
+impl Serialize for Event {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      Event::Unknown { raw, .. } => raw.serialize(serializer),
+      _ => Event::serialize(self, serializer),
+    }
+  }
+}
+impl<'de> Deserialize<'de> for Event {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    struct Known(#[serde(with = "Event")] Event);
+    decode_tagged(deserializer, |raw| {
+      Known::deserialize(raw).map(|known| known.0)
+    })
+  }
+}
+impl From<(String, ciborium::Value)> for Event {
+  fn from((tag, raw): (String, ciborium::Value)) -> Self {
+    Event::Unknown { tag, raw }
+  }
+}
+impl Event {
+  /// Id of the attachment a resource request asks for.
+  pub fn requested_resource(&self) -> Option<&Vec<u8>> {
+    match self {
+      Event::RequestResource { id } | Event::RequestImage { id } => Some(id),
+      _ => None,
+    }
+  }
+
+  /// Answers a resource request in the form the requesting bridge understands.
+  pub fn respond_resource(&self, url: ArcStr) -> Option<Event> {
+    match self {
+      Event::RequestResource { id } => Some(Event::RespondResource {
+        id: id.clone(),
+        url,
+      }),
+      Event::RequestImage { id } => Some(Event::RespondImage {
+        id: id.clone(),
+        url,
+      }),
+      _ => None,
+    }
+  }
+
+  /// Short localized text describing the event, for bridges that cannot
+  /// mirror it natively.
+  pub fn notice(&self) -> Option<String> {
+    match self {
+      Event::AddReaction { profile, emoji, .. } => Some(fl!(
+        "notice-reaction-added",
+        name = profile.display_name(),
+        emoji = emoji.fallback()
+      )),
+      Event::RemoveReaction { profile, emoji, .. } => Some(fl!(
+        "notice-reaction-removed",
+        name = profile.display_name(),
+        emoji = emoji.fallback()
+      )),
+      Event::MemberJoined { profile, .. } => {
+        Some(fl!("notice-member-joined", name = profile.display_name()))
+      }
+      Event::MemberLeft { profile } => {
+        Some(fl!("notice-member-left", name = profile.display_name()))
+      }
+      Event::MemberKicked {
+        profile,
+        operator: Some(operator),
+      } => Some(fl!(
+        "notice-member-kicked-by",
+        name = profile.display_name(),
+        operator = operator.display_name()
+      )),
+      Event::MemberKicked { profile, .. } => {
+        Some(fl!("notice-member-kicked", name = profile.display_name()))
+      }
+      Event::MemberUpdated {
+        profile,
+        previous,
+        avatar,
+      } => {
+        if profile.display_name() != previous.display_name() {
+          Some(fl!(
+            "notice-member-renamed",
+            previous = previous.display_name(),
+            name = profile.display_name()
+          ))
+        } else if avatar.is_some() {
+          Some(fl!(
+            "notice-member-avatar-changed",
+            name = profile.display_name()
+          ))
+        } else {
+          None
+        }
+      }
+      _ => None,
+    }
+  }
+
+  /// Id and url carried by a resource response.
+  pub fn into_resource_response(self) -> Option<(Vec<u8>, ArcStr)> {
+    match self {
+      Event::RespondResource { id, url } | Event::RespondImage { id, url } => Some((id, url)),
+      _ => None,
+    }
+  }
 }
 
 #[cfg(test)]
 mod test {
-  use crate::data::events::*;
+  use crate::data::{
+    events::*,
+    message::{Emoji, Profile},
+  };
   #[test]
   fn test() {
     let event = Event::RequestImage {
@@ -42,5 +253,80 @@ mod test {
     println!("{} \n check in http://cbor.me/", hex::encode(&data));
     let a = ciborium::de::from_reader::<Event, &[u8]>(&data).is_ok();
     assert!(a);
+
+    let response = event
+      .respond_resource("https://example.com/dd".into())
+      .unwrap();
+    assert!(matches!(response, Event::RespondImage { .. }));
+    let request = Event::RequestResource {
+      id: "dd".as_bytes().to_owned(),
+    };
+    let (id, url) = request
+      .respond_resource("https://example.com/dd".into())
+      .and_then(Event::into_resource_response)
+      .unwrap();
+    assert_eq!(id, b"dd");
+    assert_eq!(url, "https://example.com/dd");
+
+    let reaction = Event::AddReaction {
+      id: "dd".as_bytes().to_owned(),
+      profile: Profile {
+        id: 10000i64.to_be_bytes().to_vec(),
+        username: None,
+        nick: Some("nick".into()),
+      },
+      emoji: Emoji::Custom {
+        id: vec![1],
+        url: None,
+        fallback: ":party:".into(),
+      },
+    };
+    let notice = reaction.notice().unwrap();
+    assert!(notice.contains("nick") && notice.contains(":party:"));
+
+    let profile = |nick: &str| Profile {
+      id: 10000i64.to_be_bytes().to_vec(),
+      username: None,
+      nick: Some(nick.into()),
+    };
+    let renamed = Event::MemberUpdated {
+      profile: profile("new"),
+      previous: profile("old"),
+      avatar: None,
+    };
+    let notice = renamed.notice().unwrap();
+    assert!(notice.contains("new") && notice.contains("old"));
+    let unchanged = Event::MemberUpdated {
+      profile: profile("same"),
+      previous: profile("same"),
+      avatar: None,
+    };
+    assert!(unchanged.notice().is_none());
+
+    let unknown = ciborium::Value::Map(vec![("t".into(), "poll".into())]);
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(&unknown, &mut data).unwrap();
+    let event = ciborium::de::from_reader::<Event, &[u8]>(&data).unwrap();
+    assert!(matches!(&event, Event::Unknown { tag, .. } if tag == "poll"));
+    let mut again = Vec::new();
+    ciborium::ser::into_writer(&event, &mut again).unwrap();
+    assert_eq!(data, again);
+
+    // a known event holding an unknown status is kept under its own tag
+    let presence = |status: &str| {
+      let presence = ciborium::Value::Map(vec![
+        ("t".into(), "presence".into()),
+        (
+          "profile".into(),
+          ciborium::Value::Map(vec![("id".into(), ciborium::Value::Bytes(vec![1]))]),
+        ),
+        ("status".into(), status.into()),
+      ]);
+      let mut data = Vec::new();
+      ciborium::ser::into_writer(&presence, &mut data).unwrap();
+      ciborium::de::from_reader::<Event, &[u8]>(&data)
+    };
+    assert!(matches!(presence("idle"), Ok(Event::Presence { .. })));
+    assert!(matches!(presence("away"), Ok(Event::Unknown { tag, .. }) if tag == "presence"));
   }
 }
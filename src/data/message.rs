@@ -1,11 +1,19 @@
-use std::convert::TryInto;
+use std::{
+  convert::TryInto,
+  fmt,
+  time::{SystemTime, UNIX_EPOCH},
+};
 
 use arcstr::ArcStr;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
 
-use crate::{OptionExt, ResultExt};
+use crate::{
+  data::{decode_tagged, rich::Span},
+  OptionExt, ResultExt,
+};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Profile {
   #[serde(with = "serde_bytes")]
   pub id: Vec<u8>,
@@ -15,6 +23,81 @@ pub struct Profile {
   pub nick: Option<String>,
 }
 
+impl Profile {
+  /// Name to show for the user, the hex encoded id when there is none.
+  pub fn display_name(&self) -> String {
+    self
+      .nick
+      .clone()
+      .or_else(|| self.username.clone())
+      .unwrap_or_else(|| hex::encode(&self.id))
+  }
+}
+
+/// Emoji of a reaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "t")]
+pub enum Emoji {
+  Unicode {
+    content: String,
+  },
+  /// Custom emoji of the sending platform.
+  Custom {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    url: Option<ArcStr>,
+    /// Shown by bridges that cannot display the emoji, e.g. `:party_parrot:`.
+    fallback: String,
+  },
+}
+impl Emoji {
+  pub fn fallback(&self) -> &str {
+    match self {
+      Emoji::Unicode { content } => content,
+      Emoji::Custom { fallback, .. } => fallback,
+    }
+  }
+}
+
+/// Key of an attachment that was encrypted before it got uploaded, travelling
+/// inside the encrypted message only.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileSecret {
+  #[serde(with = "zeroizing_bytes")]
+  pub key: Zeroizing<Vec<u8>>,
+  /// SHA-256 of the plaintext file.
+  #[serde(with = "serde_bytes")]
+  pub hash: Vec<u8>,
+}
+impl fmt::Debug for FileSecret {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("FileSecret")
+      .field("key", &"***")
+      .field("hash", &self.hash)
+      .finish()
+  }
+}
+
+mod zeroizing_bytes {
+  use serde::{Deserializer, Serializer};
+  use zeroize::Zeroizing;
+
+  pub fn serialize<S>(bytes: &Zeroizing<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serde_bytes::serialize(bytes.as_slice(), serializer)
+  }
+
+  pub fn deserialize<'de, D>(deserializer: D) -> Result<Zeroizing<Vec<u8>>, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    serde_bytes::deserialize(deserializer).map(Zeroizing::new)
+  }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct Message {
@@ -25,7 +108,24 @@ pub struct Message {
   pub id: Vec<u8>,
   #[serde(with = "serde_bytes", skip_serializing_if = "Option::is_none", default)]
   pub reply: Option<Vec<u8>>,
+  /// Id of the message this one edits. Its chain replaces the whole chain of
+  /// the original, which receivers look up with
+  /// [`Db::get_edit_target`](crate::db::Db::get_edit_target).
+  #[serde(with = "serde_bytes", skip_serializing_if = "Option::is_none", default)]
+  pub edit: Option<Vec<u8>>,
   pub chain: Vec<MessageType>,
+  /// Milliseconds since the Unix epoch at which it was sent on its platform.
+  #[serde(skip_serializing_if = "Option::is_none", default)]
+  pub timestamp: Option<u64>,
+  /// Platform it was sent on, e.g. `qq`, `telegram` or `discord`.
+  #[serde(skip_serializing_if = "Option::is_none", default)]
+  pub platform: Option<ArcStr>,
+  /// Name of the bridge that brought it into the network, filled in by
+  /// [`Packet::new`](crate::data::Packet::new). It is only claimed by the
+  /// sender, [`Opened::sender`](crate::data::Opened::sender) is the verified
+  /// one.
+  #[serde(skip_serializing_if = "Option::is_none", default)]
+  pub bridge: Option<ArcStr>,
 }
 impl Message {
   pub fn new(profile: Profile, id: i32, from: Vec<u8>, chain: Vec<MessageType>) -> Self {
@@ -34,7 +134,28 @@ impl Message {
       id: id.to_be_bytes().to_vec(),
       from,
       reply: None,
+      edit: None,
       chain,
+      timestamp: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .ok(),
+      platform: None,
+      bridge: None,
+    }
+  }
+
+  /// Replacement for the message `original`, carrying its new content.
+  pub fn new_edit(
+    profile: Profile,
+    id: i32,
+    from: Vec<u8>,
+    original: Vec<u8>,
+    chain: Vec<MessageType>,
+  ) -> Self {
+    Message {
+      edit: Some(original),
+      ..Message::new(profile, id, from, chain)
     }
   }
 
@@ -42,13 +163,34 @@ impl Message {
     i64::from_be_bytes(self.id.clone().try_into().ignore()?).some()
   }
 }
+/// How deep [`MessageType::Forward`] bundles may be nested in each other.
+/// Payloads nesting deeper are rejected.
+pub const MAX_FORWARD_DEPTH: usize = 4;
+
+/// Message inside a [`MessageType::Forward`] bundle.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "snake_case", tag = "t")]
+pub struct ForwardedMessage {
+  pub profile: Profile,
+  /// Milliseconds since the Unix epoch at which it was originally sent.
+  #[serde(skip_serializing_if = "Option::is_none", default)]
+  pub timestamp: Option<u64>,
+  pub chain: Vec<MessageType>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "t", remote = "Self")]
 #[non_exhaustive]
 pub enum MessageType {
   Text {
     content: String,
   },
+  /// Formatted text, bridges that cannot render it send
+  /// [`to_plain_text`](crate::data::rich::to_plain_text) instead.
+  Rich {
+    spans: Vec<Span>,
+  },
+  /// Edit that does not say which message it replaces, sent by bridges
+  /// predating [`Message::edit`].
   Edit {
     content: String,
   },
@@ -57,18 +199,162 @@ pub enum MessageType {
     id: Vec<u8>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     url: Option<ArcStr>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    secret: Option<FileSecret>,
   },
   Sticker {
     #[serde(with = "serde_bytes")]
     id: Vec<u8>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     url: Option<ArcStr>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    secret: Option<FileSecret>,
+  },
+  /// Voice note or audio file. Without `url` it is fetched from the sending
+  /// bridge through [`Res::file`](crate::res::Res::file) like an image.
+  Audio {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    url: Option<ArcStr>,
+    /// Length in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    duration: Option<u64>,
+    /// MIME type including the codec, e.g. `audio/ogg; codecs=opus`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    mime: Option<ArcStr>,
+    /// Recorded as a voice note rather than sent as a music file.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    voice: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    secret: Option<FileSecret>,
+  },
+  /// Video clip. Bridges that cannot play it show `thumbnail`, which is
+  /// fetched through [`Res::file`](crate::res::Res::file) like an image, and
+  /// link to the video.
+  Video {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    url: Option<ArcStr>,
+    /// Length in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    height: Option<u32>,
+    /// Size in bytes.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    mime: Option<ArcStr>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    secret: Option<FileSecret>,
+    /// Resource id of a still image standing in for the video.
+    #[serde(with = "serde_bytes", skip_serializing_if = "Option::is_none", default)]
+    thumbnail: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    thumbnail_secret: Option<FileSecret>,
   },
+  /// Document or any other attachment, fetched through
+  /// [`Res::file`](crate::res::Res::file) like an image.
+  File {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    name: ArcStr,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    url: Option<ArcStr>,
+    /// Size in bytes.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    mime: Option<ArcStr>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    secret: Option<FileSecret>,
+  },
+  /// Mention of a user, to be turned into a native mention by bridges that
+  /// know the user and shown as `@display` by the others.
+  Mention {
+    profile: Profile,
+    /// Name the user was mentioned by on the sending platform.
+    display: String,
+  },
+  /// Mention of everyone in the room.
+  MentionAll,
+  /// Bundle of forwarded messages, such as a QQ merged forward.
+  Forward {
+    messages: Vec<ForwardedMessage>,
+  },
+  /// Segment added by a newer client, kept as it was received so that it can
+  /// be passed on or shown as a placeholder.
+  #[serde(skip)]
+  Unknown {
+    tag: String,
+    raw: ciborium::Value,
+  },
+}
+impl Serialize for MessageType {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      MessageType::Unknown { raw, .. } => raw.serialize(serializer),
+      _ => MessageType::serialize(self, serializer),
+    }
+  }
+}
+impl<'de> Deserialize<'de> for MessageType {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    struct Known(#[serde(with = "MessageType")] MessageType);
+    decode_tagged(deserializer, |raw| {
+      Known::deserialize(raw).map(|known| known.0)
+    })
+  }
+}
+impl From<(String, ciborium::Value)> for MessageType {
+  fn from((tag, raw): (String, ciborium::Value)) -> Self {
+    MessageType::Unknown { tag, raw }
+  }
+}
+impl MessageType {
+  /// Number of [`MessageType::Forward`] bundles nested in this segment.
+  pub fn forward_depth(&self) -> usize {
+    match self {
+      MessageType::Forward { messages } => {
+        1 + messages
+          .iter()
+          .map(|message| chain_forward_depth(&message.chain))
+          .max()
+          .unwrap_or(0)
+      }
+      _ => 0,
+    }
+  }
+}
+
+pub fn chain_forward_depth(chain: &[MessageType]) -> usize {
+  chain
+    .iter()
+    .map(MessageType::forward_depth)
+    .max()
+    .unwrap_or(0)
 }
 
 #[cfg(test)]
 mod test {
-  use crate::data::message::{Message, MessageType, Profile};
+  use crate::data::{
+    message::{
+      chain_forward_depth, ForwardedMessage, Message, MessageType, Profile, MAX_FORWARD_DEPTH,
+    },
+    rich::Span,
+    Payload,
+  };
   #[test]
   fn test() {
     let message = Message {
@@ -88,9 +374,56 @@ mod test {
         MessageType::Image {
           id: Vec::from("id"),
           url: None,
+          secret: None,
+        },
+        MessageType::Audio {
+          id: Vec::from("audio"),
+          url: None,
+          duration: Some(3200),
+          mime: Some("audio/ogg; codecs=opus".into()),
+          voice: true,
+          secret: None,
+        },
+        MessageType::Video {
+          id: Vec::from("video"),
+          url: Some("https://example.com/video.mp4".into()),
+          duration: Some(12000),
+          width: Some(1280),
+          height: Some(720),
+          size: Some(1 << 20),
+          mime: Some("video/mp4".into()),
+          secret: None,
+          thumbnail: Some(Vec::from("thumbnail")),
+          thumbnail_secret: None,
+        },
+        MessageType::File {
+          id: Vec::from("file"),
+          name: "report.pdf".into(),
+          url: None,
+          size: Some(4096),
+          mime: Some("application/pdf".into()),
+          secret: None,
+        },
+        MessageType::Mention {
+          profile: Profile {
+            id: 10000i64.to_be_bytes().to_vec(),
+            username: None,
+            nick: Some("nick".into()),
+          },
+          display: "card name".into(),
+        },
+        MessageType::MentionAll,
+        MessageType::Rich {
+          spans: vec![Span::Bold {
+            children: vec![Span::text("this is bold")],
+          }],
         },
       ],
       reply: None,
+      edit: None,
+      timestamp: Some(1_700_000_000_000),
+      platform: Some("qq".into()),
+      bridge: None,
       from: 12113i64.to_be_bytes().to_vec(),
     };
 
@@ -100,4 +433,108 @@ mod test {
     let a = ciborium::de::from_reader::<Message, &[u8]>(&data).is_ok();
     assert!(a);
   }
+
+  #[test]
+  fn test_unknown() {
+    let segment = |tag: &str| {
+      ciborium::Value::Map(vec![
+        ("t".into(), tag.into()),
+        ("id".into(), ciborium::Value::Bytes(vec![1])),
+      ])
+    };
+    let profile = Profile {
+      id: 10000i64.to_be_bytes().to_vec(),
+      username: None,
+      nick: None,
+    };
+    let chain = vec![
+      MessageType::Text {
+        content: "known".to_string(),
+      },
+      MessageType::Unknown {
+        tag: "hologram".into(),
+        raw: segment("hologram"),
+      },
+    ];
+    let data = Payload::from(Message::new(profile.clone(), 1, vec![1], chain))
+      .to_cbor()
+      .unwrap();
+    let Payload::MsgPayload(message) = Payload::from_cbor(&data).unwrap() else {
+      panic!("not a message");
+    };
+    assert!(matches!(&message.chain[0], MessageType::Text { content } if content == "known"));
+    assert!(matches!(&message.chain[1], MessageType::Unknown { tag, .. } if tag == "hologram"));
+
+    // a known segment that is malformed is still an error
+    let malformed = vec![MessageType::Unknown {
+      tag: "text".into(),
+      raw: segment("text"),
+    }];
+    let data = Payload::from(Message::new(profile.clone(), 2, vec![1], malformed))
+      .to_cbor()
+      .unwrap();
+    assert!(Payload::from_cbor(&data).is_err());
+
+    // one holding something unknown further down is kept as a whole
+    let span = ciborium::Value::Map(vec![("t".into(), "sparkle".into())]);
+    let nested = vec![
+      MessageType::Unknown {
+        tag: "rich".into(),
+        raw: ciborium::Value::Map(vec![
+          ("t".into(), "rich".into()),
+          ("spans".into(), ciborium::Value::Array(vec![span])),
+        ]),
+      },
+      MessageType::Text {
+        content: "known".to_string(),
+      },
+    ];
+    let data = Payload::from(Message::new(profile, 3, vec![1], nested))
+      .to_cbor()
+      .unwrap();
+    let Payload::MsgPayload(message) = Payload::from_cbor(&data).unwrap() else {
+      panic!("not a message");
+    };
+    assert!(matches!(&message.chain[0], MessageType::Unknown { tag, .. } if tag == "rich"));
+    assert!(matches!(&message.chain[1], MessageType::Text { content } if content == "known"));
+  }
+
+  #[test]
+  fn test_forward() {
+    let profile = Profile {
+      id: 10000i64.to_be_bytes().to_vec(),
+      username: None,
+      nick: None,
+    };
+    let mut chain = vec![MessageType::Text {
+      content: "forwarded".to_string(),
+    }];
+    for _ in 0..MAX_FORWARD_DEPTH {
+      chain = vec![MessageType::Forward {
+        messages: vec![ForwardedMessage {
+          profile: profile.clone(),
+          timestamp: Some(1_700_000_000_000),
+          chain,
+        }],
+      }];
+    }
+    let message = Message::new(profile.clone(), 1, vec![1], chain.clone());
+    assert_eq!(chain_forward_depth(&message.chain), MAX_FORWARD_DEPTH);
+    let data = Payload::from(message).to_cbor().unwrap();
+    assert!(Payload::from_cbor(&data).is_ok());
+
+    let too_deep = Message::new(
+      profile.clone(),
+      2,
+      vec![1],
+      vec![MessageType::Forward {
+        messages: vec![ForwardedMessage {
+          profile,
+          timestamp: None,
+          chain,
+        }],
+      }],
+    );
+    assert!(Payload::from(too_deep).to_cbor().is_err());
+  }
 }
@@ -1,23 +1,42 @@
 pub mod events;
 pub mod message;
+mod raw;
+pub mod rich;
 
-use aes_gcm_siv::aead::Aead;
-use color_eyre::eyre::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ciborium::{tag::Required, Value};
+use color_eyre::eyre::{bail, Result};
 use nats::Subject;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
-use self::{events::Event, message::Message};
-use crate::{cipher::CIPHER, OkExt};
+use self::{
+  events::Event,
+  message::{chain_forward_depth, Message, MAX_FORWARD_DEPTH},
+  raw::{DecodeError, Raw},
+};
+use crate::{
+  client::{MesagistoClient, DEFAULT_CLIENT},
+  identity::{Sender, Signature},
+  OkExt, OptionExt,
+};
+
+/// CBOR tag marking a [`Frame`]. Plaintext without it is a bare [`Payload`]
+/// sent by a bridge predating signatures.
+pub const FRAME_TAG: u64 = 0x6d7367;
 
 #[derive(Debug)]
 pub struct Packet {
   pub content: Vec<u8>,
   pub room_id: Uuid,
   pub reply: Option<Subject>,
+  /// Result of opening the packet with the client that received it, filled
+  /// in before the packet handler sees it.
+  pub opened: Option<Opened>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "t")]
 pub enum Payload {
   #[serde(rename = "m")]
@@ -25,6 +44,44 @@ pub enum Payload {
   #[serde(rename = "e")]
   EventPayload(Event),
 }
+
+/// What gets encrypted into a [`Packet`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Frame {
+  #[serde(with = "serde_bytes")]
+  pub body: Vec<u8>,
+  #[serde(skip_serializing_if = "Option::is_none", default)]
+  pub signature: Option<Signature>,
+  /// Milliseconds since the Unix epoch at which the packet was sealed.
+  #[serde(skip_serializing_if = "Option::is_none", default)]
+  pub ts: Option<u64>,
+  /// Random id, unique per packet, used to drop replays.
+  #[serde(with = "serde_bytes", skip_serializing_if = "Option::is_none", default)]
+  pub id: Option<Vec<u8>>,
+}
+impl Frame {
+  /// Replay stamp covered by the signature, empty for frames without one.
+  pub fn stamp(&self) -> Vec<u8> {
+    let mut stamp = Vec::new();
+    if let Some(ts) = self.ts {
+      stamp.extend_from_slice(&ts.to_be_bytes());
+    }
+    if let Some(id) = &self.id {
+      stamp.extend_from_slice(id);
+    }
+    stamp
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Opened {
+  pub payload: Payload,
+  /// Bridge that signed the packet, `None` for unsigned packets.
+  pub sender: Option<Sender>,
+  pub ts: Option<u64>,
+  pub id: Option<Vec<u8>>,
+}
+
 impl From<Message> for Payload {
   fn from(value: Message) -> Self {
     Self::MsgPayload(value)
@@ -38,39 +95,159 @@ impl From<Event> for Payload {
 
 impl Packet {
   pub fn new(room: Uuid, payload: Payload) -> Result<Self> {
+    Self::new_with(&DEFAULT_CLIENT, room, payload)
+  }
+
+  pub fn new_with(client: &MesagistoClient, room: Uuid, mut payload: Payload) -> Result<Self> {
+    if let Payload::MsgPayload(message) = &mut payload {
+      message
+        .bridge
+        .get_or_insert_with(|| client.identity.name.clone());
+    }
+    let mut frame = Frame {
+      body: payload.to_cbor()?,
+      signature: None,
+      ts: (SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64).some(),
+      id: Uuid::new_v4().as_bytes().to_vec().some(),
+    };
+    frame.signature = client
+      .identity
+      .sign(&room, &frame.stamp(), &frame.body)
+      .some();
     let mut bytes = Vec::new();
-    ciborium::ser::into_writer(&payload, &mut bytes)?;
+    ciborium::ser::into_writer(&Required::<_, FRAME_TAG>(frame), &mut bytes)?;
 
-    let ciphertext = CIPHER.encrypt(&CIPHER.nonce, bytes.as_ref())?;
     Self {
-      content: ciphertext,
+      content: client.cipher.seal(&room, &bytes)?,
       room_id: room,
       reply: None,
+      opened: None,
     }
     .ok()
   }
 
   pub fn decrypt(&self) -> Result<Payload> {
-    let plaintext = CIPHER.decrypt(&CIPHER.nonce, self.content.as_ref())?;
-    ciborium::de::from_reader::<Payload, &[u8]>(&plaintext)?.ok()
+    self.decrypt_with(&DEFAULT_CLIENT)
+  }
+
+  pub fn decrypt_with(&self, client: &MesagistoClient) -> Result<Payload> {
+    Ok(self.open_with(client)?.payload)
+  }
+
+  /// Decrypts the packet and verifies who sent it, failing on signatures that
+  /// do not check out or are not trusted. Packets that were already opened on
+  /// receipt are not opened again.
+  pub fn open(&self) -> Result<Opened> {
+    self.open_with(&DEFAULT_CLIENT)
+  }
+
+  pub fn open_with(&self, client: &MesagistoClient) -> Result<Opened> {
+    match &self.opened {
+      Some(opened) => Ok(opened.clone()),
+      None => self.open_uncached(client),
+    }
+  }
+
+  /// [`Packet::open`] without copying the result out of a received packet.
+  pub fn into_opened(self) -> Result<Opened> {
+    match self.opened {
+      Some(opened) => Ok(opened),
+      None => self.open_uncached(&DEFAULT_CLIENT),
+    }
+  }
+
+  pub(crate) fn open_uncached(&self, client: &MesagistoClient) -> Result<Opened> {
+    let plaintext = client.cipher.open(&self.room_id, &self.content)?;
+    let frame = match ciborium::de::from_reader::<Required<Frame, FRAME_TAG>, &[u8]>(&plaintext) {
+      Ok(Required(frame)) => frame,
+      Err(_) => Frame {
+        body: plaintext,
+        signature: None,
+        ts: None,
+        id: None,
+      },
+    };
+    let sender = match &frame.signature {
+      Some(signature) => client
+        .identity
+        .verify(&self.room_id, signature, &frame.stamp(), &frame.body)?
+        .some(),
+      None => {
+        client.identity.check_unsigned()?;
+        None
+      }
+    };
+    Opened {
+      payload: Payload::from_cbor(&frame.body)?,
+      sender,
+      ts: frame.ts,
+      id: frame.id,
+    }
+    .ok()
+  }
+}
+/// Decodes an internally tagged enum through its derived `decode`. Tags of
+/// variants added by newer clients, whether of the enum itself or of an enum
+/// nested in one of its variants, leave the whole value as it was received
+/// under its own tag. Malformed payloads are still errors.
+pub(crate) fn decode_tagged<'de, D, T, F>(deserializer: D, decode: F) -> Result<T, D::Error>
+where
+  D: Deserializer<'de>,
+  F: FnOnce(Raw<'_>) -> Result<T, DecodeError>,
+  T: From<(String, Value)>,
+{
+  let raw = Value::deserialize(deserializer)?;
+  let tag = raw
+    .as_map()
+    .and_then(|map| {
+      map
+        .iter()
+        .find(|(key, _)| key.as_text() == Some("t"))
+        .and_then(|(_, tag)| tag.as_text())
+    })
+    .ok_or_else(|| de::Error::missing_field("t"))?
+    .to_owned();
+  match decode(Raw(&raw)) {
+    Ok(known) => Ok(known),
+    Err(DecodeError::UnknownVariant) => Ok(T::from((tag, raw))),
+    Err(e) => Err(de::Error::custom(e)),
   }
 }
+
 impl Payload {
   pub fn to_cbor(&self) -> Result<Vec<u8>> {
+    self.check_depth()?;
     let mut data = Vec::new();
     ciborium::ser::into_writer(&self, &mut data)?;
     Ok(data)
   }
 
   pub fn from_cbor(data: &[u8]) -> Result<Payload> {
-    ciborium::de::from_reader::<Payload, &[u8]>(data)?.ok()
+    let payload = ciborium::de::from_reader::<Payload, &[u8]>(data)?;
+    payload.check_depth()?;
+    payload.ok()
+  }
+
+  fn check_depth(&self) -> Result<()> {
+    if let Payload::MsgPayload(message) = self {
+      let depth = chain_forward_depth(&message.chain);
+      if depth > MAX_FORWARD_DEPTH {
+        bail!("Forwarded messages are nested {} levels deep", depth);
+      }
+    }
+    Ok(())
   }
 }
 #[cfg(test)]
 mod test {
+  use uuid::Uuid;
+
   use crate::{
     cipher::CIPHER,
-    data::message::{self, Message},
+    data::{
+      message::{self, Message},
+      Opened, Packet,
+    },
   };
   #[test]
   fn test() {
@@ -84,6 +261,10 @@ mod test {
       },
       id: Vec::from("id"),
       reply: None,
+      edit: None,
+      timestamp: None,
+      platform: None,
+      bridge: None,
       chain: vec![
         message::MessageType::Text {
           content: "this is text".to_string(),
@@ -98,5 +279,20 @@ mod test {
     println!("{}", hex::encode(&payload.to_cbor().unwrap()));
     let packet2 = Payload::from_cbor(&payload.to_cbor().unwrap());
     assert!(packet2.is_ok());
+
+    // a received packet is not opened a second time
+    let packet = Packet {
+      content: b"not sealed".to_vec(),
+      room_id: Uuid::nil(),
+      reply: None,
+      opened: Some(Opened {
+        payload: packet2.unwrap(),
+        sender: None,
+        ts: None,
+        id: None,
+      }),
+    };
+    assert!(packet.decrypt().is_ok());
+    assert!(packet.into_opened().is_ok());
   }
 }
@@ -0,0 +1,121 @@
+use std::fmt::{self, Display, Formatter};
+
+use ciborium::Value;
+use serde::{
+  de::{
+    self,
+    value::{MapDeserializer, SeqDeserializer},
+    IntoDeserializer, Visitor,
+  },
+  forward_to_deserialize_any, Deserializer,
+};
+
+/// Why a [`Raw`] value could not be decoded.
+#[derive(Debug)]
+pub(crate) enum DecodeError {
+  /// Some enum, at any depth, met a tag none of its derived variants carries.
+  UnknownVariant,
+  Malformed(String),
+}
+impl Display for DecodeError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::UnknownVariant => f.write_str("unknown variant"),
+      DecodeError::Malformed(msg) => f.write_str(msg),
+    }
+  }
+}
+impl std::error::Error for DecodeError {}
+impl de::Error for DecodeError {
+  fn custom<T: Display>(msg: T) -> Self {
+    DecodeError::Malformed(msg.to_string())
+  }
+
+  fn unknown_variant(_variant: &str, _expected: &'static [&'static str]) -> Self {
+    DecodeError::UnknownVariant
+  }
+}
+
+/// Already parsed CBOR handed to derived impls again. Unlike deserializing
+/// the [`Value`] itself, derived enums report unknown tags through
+/// [`DecodeError::UnknownVariant`] instead of an error message.
+#[derive(Clone, Copy)]
+pub(crate) struct Raw<'a>(pub &'a Value);
+
+impl<'de, 'a> Deserializer<'de> for Raw<'a> {
+  type Error = DecodeError;
+
+  forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+    identifier ignored_any
+  }
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Value::Integer(int) => match (u64::try_from(*int), i64::try_from(*int)) {
+        (Ok(int), _) => visitor.visit_u64(int),
+        (_, Ok(int)) => visitor.visit_i64(int),
+        _ => visitor.visit_i128(i128::from(*int)),
+      },
+      Value::Bytes(bytes) => visitor.visit_bytes(bytes),
+      Value::Float(float) => visitor.visit_f64(*float),
+      Value::Text(text) => visitor.visit_str(text),
+      Value::Bool(bool) => visitor.visit_bool(*bool),
+      Value::Null => visitor.visit_unit(),
+      Value::Tag(_, inner) => Raw(inner).deserialize_any(visitor),
+      Value::Array(items) => visitor.visit_seq(SeqDeserializer::new(items.iter().map(Raw))),
+      Value::Map(entries) => visitor.visit_map(MapDeserializer::new(
+        entries.iter().map(|(key, value)| (Raw(key), Raw(value))),
+      )),
+      _ => Err(de::Error::custom("unsupported CBOR value")),
+    }
+  }
+
+  fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, DecodeError>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Value::Null => visitor.visit_none(),
+      _ => visitor.visit_some(self),
+    }
+  }
+
+  fn deserialize_newtype_struct<V>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, DecodeError>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, DecodeError>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Value::Text(text) => visitor.visit_enum(text.as_str().into_deserializer()),
+      _ => self.deserialize_any(visitor),
+    }
+  }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, DecodeError> for Raw<'a> {
+  type Deserializer = Self;
+
+  fn into_deserializer(self) -> Self {
+    self
+  }
+}
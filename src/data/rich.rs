@@ -0,0 +1,152 @@
+use arcstr::ArcStr;
+use serde::{Deserialize, Serialize};
+
+/// Node of the formatted text carried by [`MessageType::Rich`].
+///
+/// [`MessageType::Rich`]: crate::data::message::MessageType::Rich
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "t")]
+#[non_exhaustive]
+pub enum Span {
+  Text {
+    content: String,
+  },
+  Bold {
+    children: Vec<Span>,
+  },
+  Italic {
+    children: Vec<Span>,
+  },
+  Underline {
+    children: Vec<Span>,
+  },
+  Strikethrough {
+    children: Vec<Span>,
+  },
+  /// Hidden until the reader reveals it.
+  Spoiler {
+    children: Vec<Span>,
+  },
+  Link {
+    url: ArcStr,
+    children: Vec<Span>,
+  },
+  /// Inline code.
+  Code {
+    content: String,
+  },
+  CodeBlock {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    language: Option<String>,
+    content: String,
+  },
+}
+impl Span {
+  pub fn text<S: Into<String>>(content: S) -> Self {
+    Span::Text {
+      content: content.into(),
+    }
+  }
+
+  /// Nested spans, empty for the leaves.
+  pub fn children(&self) -> &[Span] {
+    match self {
+      Span::Bold { children }
+      | Span::Italic { children }
+      | Span::Underline { children }
+      | Span::Strikethrough { children }
+      | Span::Spoiler { children }
+      | Span::Link { children, .. } => children,
+      Span::Text { .. } | Span::Code { .. } | Span::CodeBlock { .. } => &[],
+    }
+  }
+}
+
+/// Drops the formatting, for bridges that cannot render it. Links keep their
+/// url after the text unless the text is the url itself, code blocks stand on
+/// lines of their own.
+pub fn to_plain_text(spans: &[Span]) -> String {
+  let mut plain = String::new();
+  push_plain_text(spans, &mut plain, &mut false);
+  plain
+}
+
+/// `after_block` is set while the last thing written is a code block whose
+/// line still has to be ended before more text follows.
+fn push_plain_text(spans: &[Span], plain: &mut String, after_block: &mut bool) {
+  for span in spans {
+    match span {
+      Span::Text { content } | Span::Code { content } => push_line(content, plain, after_block),
+      Span::CodeBlock { content, .. } => {
+        if !plain.is_empty() && !plain.ends_with('\n') {
+          plain.push('\n');
+        }
+        plain.push_str(content);
+        *after_block = !content.ends_with('\n');
+      }
+      Span::Link { url, children } => {
+        let text = to_plain_text(children);
+        if text.is_empty() || text == url.as_str() {
+          push_line(url, plain, after_block);
+        } else {
+          push_line(&format!("{} ({})", text, url), plain, after_block);
+        }
+      }
+      _ => push_plain_text(span.children(), plain, after_block),
+    }
+  }
+}
+
+fn push_line(text: &str, plain: &mut String, after_block: &mut bool) {
+  if text.is_empty() {
+    return;
+  }
+  if std::mem::take(after_block) && !text.starts_with('\n') {
+    plain.push('\n');
+  }
+  plain.push_str(text);
+}
+
+#[cfg(test)]
+mod test {
+  use crate::data::rich::{to_plain_text, Span};
+  #[test]
+  fn test() {
+    let spans = vec![
+      Span::Bold {
+        children: vec![
+          Span::text("bold "),
+          Span::Italic {
+            children: vec![Span::text("and italic")],
+          },
+        ],
+      },
+      Span::text(", see "),
+      Span::Link {
+        url: "https://mesagisto.org".into(),
+        children: vec![Span::text("docs")],
+      },
+      Span::CodeBlock {
+        language: Some("rust".into()),
+        content: "fn main() {}".into(),
+      },
+    ];
+    assert_eq!(
+      to_plain_text(&spans),
+      "bold and italic, see docs (https://mesagisto.org)\nfn main() {}"
+    );
+    let block = vec![
+      Span::CodeBlock {
+        language: None,
+        content: "x = 1".into(),
+      },
+      Span::text("after"),
+    ];
+    assert_eq!(to_plain_text(&block), "x = 1\nafter");
+
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(&spans, &mut data).unwrap();
+    let decoded: Vec<Span> = ciborium::de::from_reader(data.as_slice()).unwrap();
+    assert_eq!(decoded, spans);
+  }
+}
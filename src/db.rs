@@ -1,13 +1,20 @@
 use arcstr::ArcStr;
 use color_eyre::eyre::Result;
-use dashmap::DashMap;
+use dashmap::{mapref::one::Ref, DashMap};
 use lateinit::LateInit;
+use once_cell::sync::Lazy;
 use sled::IVec;
 use tracing::error;
 
-#[derive(Singleton, Default)]
+use crate::{client::DEFAULT_CLIENT, data::message::Message};
+
+pub static DB: Lazy<&'static Db> = Lazy::new(|| &DEFAULT_CLIENT.db);
+
+#[derive(Default)]
 pub struct Db {
   image_db: LateInit<sled::Db>,
+  // signing key of this bridge and the keys trusted for others
+  identity_db: LateInit<sled::Db>,
   // message id
   mid_db_map: DashMap<Vec<u8>, sled::Db>,
 
@@ -19,9 +26,13 @@ impl Db {
 
     let options = sled::Config::default().cache_capacity(1024 * 1024);
     let image_db_path = format!("db/{}/image", db_name);
-    let image_db = options.path(image_db_path.as_str()).open().unwrap();
+    let image_db = options.clone().path(image_db_path.as_str()).open().unwrap();
     self.image_db.init(image_db);
 
+    let identity_db_path = format!("db/{}/identity", db_name);
+    let identity_db = options.path(identity_db_path.as_str()).open().unwrap();
+    self.identity_db.init(identity_db);
+
     self.db_name.init(db_name);
   }
 
@@ -46,6 +57,44 @@ impl Db {
     }
   }
 
+  pub fn get_signing_key(&self) -> Result<Option<IVec>> {
+    Ok(self.identity_db.get(b"signing-key")?)
+  }
+
+  pub fn put_signing_key<K>(&self, seed: K) -> Result<()>
+  where
+    K: Into<IVec>,
+  {
+    self.identity_db.insert(b"signing-key", seed)?;
+    self.identity_db.flush()?;
+    Ok(())
+  }
+
+  /// Remembers `key` for the bridge `name` unless a key is already known for
+  /// it, returning the key trusted from now on.
+  pub fn trust_first_key(&self, name: &str, key: &[u8]) -> Result<IVec> {
+    let trust = self.identity_db.open_tree("trust")?;
+    match trust.compare_and_swap(name, None::<&[u8]>, Some(key))? {
+      Ok(()) => Ok(key.into()),
+      Err(e) => Ok(e.current.unwrap_or_else(|| key.into())),
+    }
+  }
+
+  fn msg_id_db(&self, target: &[u8]) -> Ref<'_, Vec<u8>, sled::Db> {
+    if let Some(msg_id_db) = self.mid_db_map.get(target) {
+      return msg_id_db;
+    }
+    self
+      .mid_db_map
+      .entry(target.to_vec())
+      .or_insert_with(|| {
+        let options = sled::Config::default().cache_capacity(1024 * 1024);
+        let msg_id_db_path = format!("db/{}/msg-id/{}", *self.db_name, base64_url::encode(target));
+        options.path(msg_id_db_path).open().unwrap()
+      })
+      .downgrade()
+  }
+
   pub fn put_msg_id(
     &self,
     target: Vec<u8>,
@@ -53,15 +102,7 @@ impl Db {
     id: Vec<u8>,
     reverse: bool,
   ) -> Result<()> {
-    let msg_id_db = self.mid_db_map.entry(target.clone()).or_insert_with(|| {
-      let options = sled::Config::default().cache_capacity(1024 * 1024);
-      let msg_id_db_path = format!(
-        "db/{}/msg-id/{}",
-        *self.db_name,
-        base64_url::encode(&target)
-      );
-      options.path(msg_id_db_path).open().unwrap()
-    });
+    let msg_id_db = self.msg_id_db(&target);
     msg_id_db.insert(&uid, id.clone())?;
     if reverse {
       msg_id_db.insert(&id, uid)?;
@@ -70,14 +111,34 @@ impl Db {
   }
 
   pub fn get_msg_id(&self, target: &[u8], id: &[u8]) -> Result<Option<Vec<u8>>> {
-    let msg_id_db = match self.mid_db_map.get(target) {
-      Some(v) => v,
-      None => return Ok(None),
-    };
+    let msg_id_db = self.msg_id_db(target);
     let id = match msg_id_db.get(id)? {
       Some(v) => v.to_vec(),
       None => return Ok(None),
     };
     Ok(Some(id))
   }
+
+  /// Local id of the message `message` edits, so the edit can be applied in
+  /// place.
+  pub fn get_edit_target(&self, target: &[u8], message: &Message) -> Result<Option<Vec<u8>>> {
+    match &message.edit {
+      Some(original) => self.get_msg_id(target, original),
+      None => Ok(None),
+    }
+  }
+
+  /// Forgets the mapping of a deleted message in both directions, returning
+  /// the id it was mapped to.
+  pub fn remove_msg_id(&self, target: &[u8], id: &[u8]) -> Result<Option<Vec<u8>>> {
+    let msg_id_db = self.msg_id_db(target);
+    let mapped = match msg_id_db.remove(id)? {
+      Some(v) => v.to_vec(),
+      None => return Ok(None),
+    };
+    if msg_id_db.get(&mapped)?.as_deref() == Some(id) {
+      msg_id_db.remove(&mapped)?;
+    }
+    Ok(Some(mapped))
+  }
 }
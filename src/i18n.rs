@@ -16,6 +16,8 @@ pub static LANGUAGE_LOADER: Lazy<FluentLanguageLoader> = Lazy::new(|| {
     .load_fallback_language(&Localizations)
     .expect("Error while loading fallback language");
   _ = i18n_embed::select(&loader, &Localizations, &requested_languages);
+  // notices are sent into chats, where bidi isolation marks would show up
+  loader.set_use_isolating(false);
   loader
 });
 
@@ -0,0 +1,241 @@
+use std::{collections::HashMap, sync::Weak};
+
+use arcstr::ArcStr;
+use color_eyre::eyre::{bail, eyre, Result};
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use lateinit::LateInit;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::client::{upgrade, MesagistoClient, DEFAULT_CLIENT};
+
+pub static IDENTITY: Lazy<&'static Identity> = Lazy::new(|| &DEFAULT_CLIENT.identity);
+
+/// Decides which signing keys of other bridges are believed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "t")]
+pub enum TrustPolicy {
+  /// Remembers the first key every bridge name signs with and rejects any
+  /// other key claiming that name afterwards.
+  TrustOnFirstUse {
+    /// Rejects unsigned packets as well. Turn it on once every bridge of the
+    /// network signs, otherwise anyone holding the network key can still
+    /// inject packets by leaving the signature out.
+    #[serde(default)]
+    require_signatures: bool,
+  },
+  /// Only accepts the listed bridges, mapping their name to the hex encoded
+  /// public key. Unsigned packets are rejected.
+  Pinned { keys: HashMap<ArcStr, ArcStr> },
+}
+
+impl Default for TrustPolicy {
+  fn default() -> Self {
+    Self::TrustOnFirstUse {
+      require_signatures: false,
+    }
+  }
+}
+
+/// Signature a bridge attaches to every packet it sends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Signature {
+  pub name: ArcStr,
+  #[serde(with = "serde_bytes")]
+  pub key: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  pub sig: Vec<u8>,
+}
+
+/// Bridge whose signature over a packet has been verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sender {
+  pub name: ArcStr,
+  pub key: [u8; 32],
+}
+
+#[derive(Default)]
+pub struct Identity {
+  client: Weak<MesagistoClient>,
+  pub name: LateInit<ArcStr>,
+  signing_key: LateInit<SigningKey>,
+  trust: LateInit<TrustPolicy>,
+}
+impl Identity {
+  pub fn new(client: Weak<MesagistoClient>) -> Self {
+    Self {
+      client,
+      ..Default::default()
+    }
+  }
+
+  /// Loads the long-term key of this bridge, generating it on first start.
+  pub fn init(&self, name: ArcStr, trust: TrustPolicy) -> Result<()> {
+    let db = &upgrade(&self.client).db;
+    let seed = match db.get_signing_key()? {
+      Some(seed) => Zeroizing::new(<[u8; 32]>::try_from(seed.as_ref())?),
+      None => {
+        let seed = Zeroizing::new(rand::random::<[u8; 32]>());
+        db.put_signing_key(&seed[..])?;
+        seed
+      }
+    };
+    self.init_with(name, SigningKey::from_bytes(&seed), trust);
+    Ok(())
+  }
+
+  pub fn init_with(&self, name: ArcStr, signing_key: SigningKey, trust: TrustPolicy) {
+    self.name.init(name);
+    self.signing_key.init(signing_key);
+    self.trust.init(trust);
+  }
+
+  /// Hex encoded public key, for pinning this bridge on the others.
+  pub fn public_key_hex(&self) -> String {
+    hex::encode(self.signing_key.verifying_key().as_bytes())
+  }
+
+  pub fn sign(&self, room: &Uuid, stamp: &[u8], body: &[u8]) -> Signature {
+    let sig = self
+      .signing_key
+      .sign(&signed_bytes(room, &self.name, stamp, body));
+    Signature {
+      name: self.name.clone(),
+      key: self.signing_key.verifying_key().to_bytes().to_vec(),
+      sig: sig.to_bytes().to_vec(),
+    }
+  }
+
+  pub fn verify(
+    &self,
+    room: &Uuid,
+    signature: &Signature,
+    stamp: &[u8],
+    body: &[u8],
+  ) -> Result<Sender> {
+    let key: [u8; 32] = signature.key.as_slice().try_into()?;
+    let sig = ed25519_dalek::Signature::from_slice(&signature.sig)?;
+    let signed = signed_bytes(room, &signature.name, stamp, body);
+    VerifyingKey::from_bytes(&key)?.verify(&signed, &sig)?;
+    match &*self.trust {
+      TrustPolicy::TrustOnFirstUse { .. } => {
+        let db = &upgrade(&self.client).db;
+        if db.trust_first_key(&signature.name, &key)? != key {
+          bail!(
+            "Bridge {} signed with a key other than the one seen first",
+            signature.name
+          )
+        }
+      }
+      TrustPolicy::Pinned { keys } => {
+        let pinned = keys
+          .get(&signature.name)
+          .ok_or_else(|| eyre!("Bridge {} is not pinned", signature.name))?;
+        if hex::decode(pinned.as_str())? != key {
+          bail!(
+            "Bridge {} signed with a key other than the pinned one",
+            signature.name
+          )
+        }
+      }
+    }
+    Ok(Sender {
+      name: signature.name.clone(),
+      key,
+    })
+  }
+
+  /// Whether packets without a signature, sent by bridges predating them, are
+  /// still let through.
+  pub fn check_unsigned(&self) -> Result<()> {
+    match &*self.trust {
+      TrustPolicy::TrustOnFirstUse {
+        require_signatures: false,
+      } => Ok(()),
+      _ => bail!("Rejected an unsigned packet"),
+    }
+  }
+}
+
+/// The room, the claimed name and the replay stamp are signed along with the
+/// body so none of them can be swapped out.
+fn signed_bytes(room: &Uuid, name: &ArcStr, stamp: &[u8], body: &[u8]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(16 + 2 + name.len() + stamp.len() + body.len());
+  bytes.extend_from_slice(room.as_bytes());
+  bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+  bytes.extend_from_slice(name.as_bytes());
+  bytes.extend_from_slice(stamp);
+  bytes.extend_from_slice(body);
+  bytes
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+
+  use ed25519_dalek::SigningKey;
+  use uuid::Uuid;
+
+  use crate::identity::{Identity, TrustPolicy};
+  #[test]
+  fn test() {
+    let room = Uuid::new_v4();
+    let sender = Identity::default();
+    sender.init_with(
+      "qq".into(),
+      SigningKey::from_bytes(&[1; 32]),
+      TrustPolicy::default(),
+    );
+    let impostor = Identity::default();
+    impostor.init_with(
+      "qq".into(),
+      SigningKey::from_bytes(&[2; 32]),
+      TrustPolicy::default(),
+    );
+
+    let keys = HashMap::from([("qq".into(), sender.public_key_hex().into())]);
+    let receiver = Identity::default();
+    receiver.init_with(
+      "tg".into(),
+      SigningKey::from_bytes(&[3; 32]),
+      TrustPolicy::Pinned { keys },
+    );
+
+    let signature = sender.sign(&room, b"stamp", b"body");
+    let verified = receiver
+      .verify(&room, &signature, b"stamp", b"body")
+      .unwrap();
+    assert_eq!(verified.name, "qq");
+    assert!(receiver
+      .verify(&room, &signature, b"stamp", b"forged")
+      .is_err());
+    assert!(receiver
+      .verify(&room, &signature, b"other stamp", b"body")
+      .is_err());
+    assert!(receiver
+      .verify(&Uuid::new_v4(), &signature, b"stamp", b"body")
+      .is_err());
+    let forged = impostor.sign(&room, b"stamp", b"body");
+    assert!(receiver.verify(&room, &forged, b"stamp", b"body").is_err());
+    assert!(receiver.check_unsigned().is_err());
+
+    let lenient = Identity::default();
+    lenient.init_with(
+      "tg".into(),
+      SigningKey::from_bytes(&[3; 32]),
+      TrustPolicy::default(),
+    );
+    assert!(lenient.check_unsigned().is_ok());
+    let strict = Identity::default();
+    strict.init_with(
+      "tg".into(),
+      SigningKey::from_bytes(&[3; 32]),
+      TrustPolicy::TrustOnFirstUse {
+        require_signatures: true,
+      },
+    );
+    assert!(strict.check_unsigned().is_err());
+  }
+}
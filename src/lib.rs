@@ -1,40 +1,43 @@
 #![feature(fn_traits, trait_alias)]
 #![feature(async_closure)]
 #![feature(let_chains)]
-#![feature(slice_pattern)]
 use std::{
   fmt::{self, Debug, Formatter},
   ops::ControlFlow,
   sync::Arc,
+  time::Duration,
 };
 
 use arcstr::ArcStr;
-use cipher::CIPHER;
+use cipher::{CipherSuite, Kdf};
+pub use client::MesagistoClient;
+use client::DEFAULT_CLIENT;
 use color_eyre::eyre::Result;
 use dashmap::DashMap;
 use data::Packet;
-use db::DB;
 use educe::Educe;
 use futures_util::future::BoxFuture;
-use i18n::LANGUAGE_LOADER;
-use net::NET;
-use once_cell::sync::Lazy;
-use res::RES;
-use server::SERVER;
+use identity::TrustPolicy;
+use secret::Secret;
 use uuid::Uuid;
 
 pub mod cipher;
+pub mod client;
+pub mod convert;
 pub mod data;
 pub mod db;
 pub mod error;
+pub mod identity;
 pub mod net;
+pub mod render;
+pub mod replay;
 pub mod res;
+pub mod secret;
 pub mod server;
+pub mod throttle;
 
 mod i18n;
 
-#[macro_use]
-extern crate singleton;
 #[macro_use]
 extern crate derive_builder;
 
@@ -47,26 +50,63 @@ pub struct MesagistoConfig {
   #[educe(Default = "default")]
   pub name: ArcStr,
   pub proxy: Option<ArcStr>,
-  pub cipher_key: ArcStr,
+  pub cipher_key: Secret,
+  /// How `cipher_key` is stretched into the encryption key. Every bridge of a
+  /// network has to agree on it. Moving off [`Kdf::Sha256`] also moves every
+  /// room to a new subject, so all bridges of a network have to switch
+  /// together.
+  #[builder(default)]
+  pub kdf: Kdf,
+  /// Network-wide salt, required by the memory-hard KDFs.
+  #[builder(default)]
+  pub cipher_salt: Option<ArcStr>,
+  /// Key the room addresses were originally derived from. Set it to the
+  /// previous `cipher_key` when rotating so that every bridge stays on the
+  /// same subjects.
+  #[builder(default)]
+  pub origin_key: Option<Secret>,
+  /// Keys that are no longer used for sending but still accepted for
+  /// `key_grace` after startup.
+  #[builder(default)]
+  pub retired_keys: Vec<Secret>,
+  #[educe(Default(expression = Duration::from_secs(24 * 60 * 60)))]
+  #[builder(default = "Duration::from_secs(24 * 60 * 60)")]
+  pub key_grace: Duration,
+  /// Suite outgoing packets are sealed with.
+  #[builder(default)]
+  pub cipher_suite: CipherSuite,
+  /// Which signing keys of other bridges are believed.
+  #[builder(default)]
+  pub trust: TrustPolicy,
+  /// Largest clock skew tolerated between bridges before packets are dropped
+  /// as replays.
+  #[educe(Default(expression = Duration::from_secs(5 * 60)))]
+  #[builder(default = "Duration::from_secs(5 * 60)")]
+  pub replay_window: Duration,
+  /// How many packet ids are remembered for spotting duplicates.
+  #[educe(Default = 65536)]
+  #[builder(default = "65536")]
+  pub replay_cache_size: usize,
+  /// Shortest interval between two identical typing or presence events of
+  /// one user.
+  #[educe(Default(expression = Duration::from_secs(5)))]
+  #[builder(default = "Duration::from_secs(5)")]
+  pub ephemeral_interval: Duration,
   pub remote_address: Option<ArcStr>,
 }
 impl MesagistoConfig {
+  /// Joins the network with the default client behind the `CIPHER`,
+  /// `SERVER`, ... statics. Use [`MesagistoClient::connect`] for further
+  /// networks.
   pub async fn apply(self) -> Result<()> {
-    Lazy::force(&LANGUAGE_LOADER);
-    DB.init(self.name.some());
-    CIPHER.init(&self.cipher_key)?;
-    RES.init().await;
-    SERVER.init(self.remote_address).await?;
-    NET.init(self.proxy);
-    Ok(())
+    DEFAULT_CLIENT.init(self).await
   }
 
   pub fn packet_handler<F>(resolver: F)
   where
     F: Fn(Packet) -> BoxFuture<'static, Result<ControlFlow<Packet>>> + Send + Sync + 'static,
   {
-    let h = Box::new(resolver);
-    SERVER.packet_handler.init(h);
+    DEFAULT_CLIENT.packet_handler(resolver);
   }
 }
 
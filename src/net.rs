@@ -4,8 +4,13 @@ use arcstr::ArcStr;
 use color_eyre::eyre::Result;
 use futures_util::FutureExt;
 use lateinit::LateInit;
+use once_cell::sync::Lazy;
 use tokio::io::AsyncWriteExt;
 
+use crate::client::DEFAULT_CLIENT;
+
+pub static NET: Lazy<&'static Net> = Lazy::new(|| &DEFAULT_CLIENT.net);
+
 pub fn new_reqwest_builder() -> reqwest::ClientBuilder {
   let connect_timeout = Duration::from_secs(5);
   let timeout = connect_timeout + Duration::from_secs(12);
@@ -17,7 +22,7 @@ pub fn new_reqwest_builder() -> reqwest::ClientBuilder {
     .use_rustls_tls()
 }
 
-#[derive(Singleton, Default)]
+#[derive(Default)]
 pub struct Net {
   inner: LateInit<reqwest::Client>,
 }
@@ -0,0 +1,519 @@
+use arcstr::ArcStr;
+
+use crate::{
+  data::{
+    message::{Message, MessageType},
+    rich::{to_plain_text, Span},
+  },
+  fl,
+};
+
+/// Markup a [`Renderer`] produces. Line breaks are kept as `\n` in every
+/// format, bridges convert them when their platform needs to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  #[default]
+  Plain,
+  /// CommonMark, with `||spoilers||` as most chat platforms write them.
+  Markdown,
+  Html,
+}
+impl Format {
+  pub fn escape(&self, text: &str) -> String {
+    match self {
+      Format::Plain => text.to_owned(),
+      Format::Markdown => escape_markdown(text, "\\`*_[]()#+-.!|~<>"),
+      Format::Html => text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;"),
+    }
+  }
+
+  fn quote(&self, text: &str) -> String {
+    match self {
+      Format::Plain | Format::Markdown => {
+        let mut quoted = String::new();
+        for line in text.lines() {
+          quoted.push_str("> ");
+          quoted.push_str(line);
+          quoted.push('\n');
+        }
+        quoted
+      }
+      Format::Html => format!("<blockquote>{}</blockquote>", text),
+    }
+  }
+}
+
+/// Text a [`Renderer`] puts around and in place of segments. Templates are
+/// written in the target format and are not escaped, the values filled into
+/// their `{name}`, `{content}` placeholders are.
+#[derive(Debug, Clone)]
+pub struct Templates {
+  /// Put before the content, `{name}` is the display name of the sender.
+  pub sender: String,
+  /// Quote of the message replied to, `{name}` is its sender and `{content}`
+  /// its shortened plain text.
+  pub reply: String,
+  /// How many characters of the replied message are quoted.
+  pub reply_length: usize,
+  pub image: String,
+  pub sticker: String,
+  pub audio: String,
+  pub video: String,
+  /// `{name}` is the file name.
+  pub file: String,
+  pub mention_all: String,
+  /// Heading of a bundle of forwarded messages.
+  pub forward: String,
+  /// Stands in for segments added by newer clients.
+  pub unknown: String,
+}
+impl Default for Templates {
+  fn default() -> Self {
+    Self {
+      sender: "{name}: ".into(),
+      reply: "{name}: {content}".into(),
+      reply_length: 64,
+      image: format!("[{}]", fl!("render-image")),
+      sticker: format!("[{}]", fl!("render-sticker")),
+      audio: format!("[{}]", fl!("render-audio")),
+      video: format!("[{}]", fl!("render-video")),
+      file: format!("[{}] {{name}}", fl!("render-file")),
+      mention_all: format!("@{}", fl!("render-everyone")),
+      forward: format!("[{}]", fl!("render-forward")),
+      unknown: format!("[{}]", fl!("render-unknown")),
+    }
+  }
+}
+
+/// Turns messages into text for platforms, which only have to apply their own
+/// escaping on top.
+#[derive(Debug, Default, Clone)]
+pub struct Renderer {
+  pub format: Format,
+  pub templates: Templates,
+}
+impl Renderer {
+  pub fn new(format: Format) -> Self {
+    Self {
+      format,
+      templates: Default::default(),
+    }
+  }
+
+  /// Renders `message` with its sender, quoting `reply` when the bridge could
+  /// look up the message it replies to.
+  pub fn render(&self, message: &Message, reply: Option<&Message>) -> String {
+    let mut text = String::new();
+    if let Some(reply) = reply {
+      let mut content: String = self
+        .with_format(Format::Plain)
+        .render_chain(&reply.chain)
+        .replace('\n', " ");
+      if content.chars().count() > self.templates.reply_length {
+        content = content.chars().take(self.templates.reply_length).collect();
+        content.push('…');
+      }
+      let quote = fill(
+        &self.templates.reply,
+        &[
+          ("name", &self.format.escape(&reply.profile.display_name())),
+          ("content", &self.format.escape(&content)),
+        ],
+      );
+      text.push_str(&self.format.quote(&quote));
+    }
+    text.push_str(&self.sender(&message.profile.display_name()));
+    text.push_str(&self.render_chain(&message.chain));
+    text
+  }
+
+  /// Renders the segments alone, without a sender.
+  pub fn render_chain(&self, chain: &[MessageType]) -> String {
+    let mut text = String::new();
+    for segment in chain {
+      let (piece, inline) = self.render_segment(segment);
+      if !inline && !text.is_empty() {
+        if matches!(segment, MessageType::Forward { .. }) {
+          if !text.ends_with('\n') {
+            text.push('\n');
+          }
+        } else if !text.ends_with(char::is_whitespace) {
+          text.push(' ');
+        }
+      }
+      text.push_str(&piece);
+    }
+    text
+  }
+
+  pub fn render_spans(&self, spans: &[Span]) -> String {
+    if self.format == Format::Plain {
+      return to_plain_text(spans);
+    }
+    let mut text = String::new();
+    for span in spans {
+      text.push_str(&self.render_span(span));
+    }
+    text
+  }
+
+  fn with_format(&self, format: Format) -> Renderer {
+    Renderer {
+      format,
+      templates: self.templates.clone(),
+    }
+  }
+
+  fn sender(&self, name: &str) -> String {
+    fill(
+      &self.templates.sender,
+      &[("name", &self.format.escape(name))],
+    )
+  }
+
+  /// The rendered segment and whether it flows with the surrounding text.
+  fn render_segment(&self, segment: &MessageType) -> (String, bool) {
+    match segment {
+      MessageType::Text { content } | MessageType::Edit { content } => {
+        (self.format.escape(content), true)
+      }
+      MessageType::Rich { spans } => (self.render_spans(spans), true),
+      MessageType::Mention { display, .. } => (format!("@{}", self.format.escape(display)), true),
+      MessageType::MentionAll => (self.templates.mention_all.clone(), true),
+      MessageType::Image { url, .. } => (self.attachment(&self.templates.image, url), false),
+      MessageType::Sticker { url, .. } => (self.attachment(&self.templates.sticker, url), false),
+      MessageType::Audio { url, .. } => (self.attachment(&self.templates.audio, url), false),
+      MessageType::Video { url, .. } => (self.attachment(&self.templates.video, url), false),
+      MessageType::File { name, url, .. } => {
+        let placeholder = fill(&self.templates.file, &[("name", &self.format.escape(name))]);
+        (self.attachment(&placeholder, url), false)
+      }
+      MessageType::Forward { messages } => {
+        let mut quoted = String::new();
+        for message in messages {
+          if !quoted.is_empty() {
+            quoted.push('\n');
+          }
+          quoted.push_str(&self.sender(&message.profile.display_name()));
+          quoted.push_str(&self.render_chain(&message.chain));
+        }
+        let heading = format!("{}\n", self.templates.forward);
+        (heading + &self.format.quote(&quoted), false)
+      }
+      MessageType::Unknown { .. } => (self.templates.unknown.clone(), false),
+    }
+  }
+
+  fn attachment(&self, placeholder: &str, url: &Option<ArcStr>) -> String {
+    let Some(url) = url else {
+      return placeholder.to_owned();
+    };
+    match self.format {
+      Format::Plain => format!("{} {}", placeholder, url),
+      Format::Markdown => format!("[{}]({})", placeholder, markdown_url(url)),
+      Format::Html => format!(
+        "<a href=\"{}\">{}</a>",
+        self.format.escape(url),
+        placeholder
+      ),
+    }
+  }
+
+  fn render_span(&self, span: &Span) -> String {
+    let children = || self.render_spans(span.children());
+    match (self.format, span) {
+      (_, Span::Text { content }) => self.format.escape(content),
+      (Format::Markdown, Span::Bold { .. }) => wrap_markdown("**", &children()),
+      // `*` also works inside words, where `_` does not
+      (Format::Markdown, Span::Italic { .. }) => wrap_markdown("*", &children()),
+      (Format::Markdown, Span::Strikethrough { .. }) => wrap_markdown("~~", &children()),
+      (Format::Markdown, Span::Spoiler { .. }) => wrap_markdown("||", &children()),
+      (Format::Markdown, Span::Link { url, .. }) => {
+        format!("[{}]({})", children(), markdown_url(url))
+      }
+      (Format::Markdown, Span::Code { content }) => markdown_code(content),
+      (Format::Markdown, Span::CodeBlock { language, content }) => {
+        markdown_code_block(language.as_deref(), content)
+      }
+      (Format::Html, Span::Bold { .. }) => format!("<b>{}</b>", children()),
+      (Format::Html, Span::Italic { .. }) => format!("<i>{}</i>", children()),
+      (Format::Html, Span::Underline { .. }) => format!("<u>{}</u>", children()),
+      (Format::Html, Span::Strikethrough { .. }) => format!("<s>{}</s>", children()),
+      (Format::Html, Span::Spoiler { .. }) => {
+        format!("<span class=\"spoiler\">{}</span>", children())
+      }
+      (Format::Html, Span::Link { url, .. }) => {
+        format!("<a href=\"{}\">{}</a>", self.format.escape(url), children())
+      }
+      (Format::Html, Span::Code { content }) => {
+        format!("<code>{}</code>", self.format.escape(content))
+      }
+      (Format::Html, Span::CodeBlock { language, content }) => match language {
+        Some(language) => format!(
+          "<pre><code class=\"language-{}\">{}</code></pre>",
+          self.format.escape(language),
+          self.format.escape(content)
+        ),
+        None => format!("<pre><code>{}</code></pre>", self.format.escape(content)),
+      },
+      // Markdown has no underline
+      _ => children(),
+    }
+  }
+}
+
+/// Replaces the `{key}` placeholders of `template` in one pass, so that values
+/// containing placeholders are left alone.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+  let mut filled = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    filled.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let value = rest.find('}').and_then(|end| {
+      let (_, value) = values.iter().find(|(key, _)| *key == &rest[1..end])?;
+      Some((value, end))
+    });
+    match value {
+      Some((value, end)) => {
+        filled.push_str(value);
+        rest = &rest[end + 1..];
+      }
+      None => {
+        filled.push('{');
+        rest = &rest[1..];
+      }
+    }
+  }
+  filled.push_str(rest);
+  filled
+}
+
+/// Escapes the characters of `special` in Markdown text with a backslash.
+pub(crate) fn escape_markdown(text: &str, special: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    if special.contains(c) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+/// Link destination in Markdown. Whitespace and angle brackets would end or
+/// break the link, they are percent-encoded.
+pub(crate) fn markdown_url(url: &str) -> String {
+  let mut escaped = String::with_capacity(url.len());
+  for c in url.chars() {
+    match c {
+      '\\' | ')' => {
+        escaped.push('\\');
+        escaped.push(c);
+      }
+      '<' | '>' => escaped.push_str(&format!("%{:02X}", c as u32)),
+      c if c.is_whitespace() || c.is_control() => {
+        for byte in c.to_string().bytes() {
+          escaped.push_str(&format!("%{:02X}", byte));
+        }
+      }
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// Puts `delimiter` around `inner`. Whitespace at its edges goes outside, a
+/// delimiter next to whitespace is not taken for one.
+pub(crate) fn wrap_markdown(delimiter: &str, inner: &str) -> String {
+  let trimmed = inner.trim_start();
+  let content = trimmed.trim_end();
+  if content.is_empty() {
+    return inner.to_owned();
+  }
+  let lead = &inner[..inner.len() - trimmed.len()];
+  let trail = &trimmed[content.len()..];
+  format!("{lead}{delimiter}{content}{delimiter}{trail}")
+}
+
+/// Inline code fenced with more backticks than `content` has in a row.
+pub(crate) fn markdown_code(content: &str) -> String {
+  let fence = "`".repeat(longest_run(content, '`') + 1);
+  // one space on each side is stripped again when parsing
+  let padded =
+    content.starts_with(' ') && content.ends_with(' ') && !content.trim_matches(' ').is_empty();
+  let pad = if padded || content.starts_with('`') || content.ends_with('`') {
+    " "
+  } else {
+    ""
+  };
+  format!("{fence}{pad}{content}{pad}{fence}")
+}
+
+pub(crate) fn markdown_code_block(language: Option<&str>, content: &str) -> String {
+  let fence = "`".repeat(longest_run(content, '`').max(2) + 1);
+  let language = language.unwrap_or_default();
+  format!("{fence}{language}\n{content}\n{fence}")
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+  let (mut longest, mut run) = (0, 0);
+  for ch in text.chars() {
+    run = if ch == c { run + 1 } else { 0 };
+    longest = longest.max(run);
+  }
+  longest
+}
+
+#[cfg(test)]
+mod test {
+  use crate::{
+    data::{
+      message::{ForwardedMessage, Message, MessageType, Profile},
+      rich::Span,
+    },
+    render::{Format, Renderer, Templates},
+  };
+  fn profile(nick: &str) -> Profile {
+    Profile {
+      id: vec![1],
+      username: None,
+      nick: Some(nick.into()),
+    }
+  }
+  fn templates() -> Templates {
+    Templates {
+      image: "[Image]".into(),
+      forward: "[Forwarded]".into(),
+      ..Default::default()
+    }
+  }
+  #[test]
+  fn test() {
+    let message = Message::new(
+      profile("a_b"),
+      1,
+      vec![1],
+      vec![
+        MessageType::Rich {
+          spans: vec![
+            Span::Bold {
+              children: vec![Span::text("1 < 2")],
+            },
+            Span::text(" and "),
+            Span::Link {
+              url: "https://example.com/(x)".into(),
+              children: vec![Span::text("link")],
+            },
+          ],
+        },
+        MessageType::Image {
+          id: vec![2],
+          url: Some("https://example.com/a.png".into()),
+          secret: None,
+        },
+      ],
+    );
+    let reply = Message::new(
+      profile("c"),
+      2,
+      vec![1],
+      vec![MessageType::Text {
+        content: "first line\nsecond line".into(),
+      }],
+    );
+
+    let plain = Renderer {
+      format: Format::Plain,
+      templates: templates(),
+    };
+    assert_eq!(
+      plain.render(&message, Some(&reply)),
+      "> c: first line second line\na_b: 1 < 2 and link (https://example.com/(x)) [Image] https://example.com/a.png"
+    );
+    let markdown = Renderer {
+      format: Format::Markdown,
+      templates: templates(),
+    };
+    assert_eq!(
+      markdown.render(&message, None),
+      "a\\_b: **1 \\< 2** and [link](https://example.com/(x\\)) [[Image]](https://example.com/a.png)"
+    );
+    let spans = vec![
+      Span::Italic {
+        children: vec![Span::text(" spaced ")],
+      },
+      Span::Link {
+        url: "https://example.com/a b<c>".into(),
+        children: vec![Span::text("link")],
+      },
+      Span::Code {
+        content: "a``b".into(),
+      },
+    ];
+    assert_eq!(
+      markdown.render_spans(&spans),
+      " *spaced* [link](https://example.com/a%20b%3Cc%3E)```a``b```"
+    );
+    let html = Renderer {
+      format: Format::Html,
+      templates: templates(),
+    };
+    assert_eq!(
+      html.render(&message, None),
+      "a_b: <b>1 &lt; 2</b> and <a href=\"https://example.com/(x)\">link</a> <a href=\"https://example.com/a.png\">[Image]</a>"
+    );
+
+    // names are never taken for placeholders
+    let reply = Message::new(
+      profile("{content}"),
+      3,
+      vec![1],
+      vec![MessageType::Text {
+        content: "secret".into(),
+      }],
+    );
+    assert!(plain
+      .render(&message, Some(&reply))
+      .starts_with("> {content}: secret\n"));
+  }
+
+  #[test]
+  fn test_forward() {
+    let chain = vec![
+      MessageType::Text {
+        content: "look".into(),
+      },
+      MessageType::Forward {
+        messages: vec![
+          ForwardedMessage {
+            profile: profile("x"),
+            timestamp: None,
+            chain: vec![MessageType::Text {
+              content: "hello".into(),
+            }],
+          },
+          ForwardedMessage {
+            profile: profile("y"),
+            timestamp: None,
+            chain: vec![MessageType::Text {
+              content: "world".into(),
+            }],
+          },
+        ],
+      },
+    ];
+    let plain = Renderer {
+      format: Format::Plain,
+      templates: templates(),
+    };
+    assert_eq!(
+      plain.render_chain(&chain),
+      "look\n[Forwarded]\n> x: hello\n> y: world\n"
+    );
+  }
+}
@@ -0,0 +1,111 @@
+use std::{
+  collections::{HashSet, VecDeque},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::{bail, Result};
+use lateinit::LateInit;
+use once_cell::sync::Lazy;
+
+use crate::{client::DEFAULT_CLIENT, data::Opened};
+
+pub static REPLAY: Lazy<&'static Replay> = Lazy::new(|| &DEFAULT_CLIENT.replay);
+
+#[derive(Default)]
+struct Seen {
+  ids: HashSet<Vec<u8>>,
+  order: VecDeque<Vec<u8>>,
+}
+
+/// Drops packets that were already delivered or whose timestamp is too far
+/// from the local clock. The cache only has to hold the ids seen within the
+/// skew window, older packets are rejected by their timestamp anyway.
+#[derive(Default)]
+pub struct Replay {
+  pub window: LateInit<Duration>,
+  pub capacity: LateInit<usize>,
+  seen: Mutex<Seen>,
+  /// Packets dropped because their id was already seen.
+  pub duplicates: AtomicU64,
+  /// Packets dropped because they were sealed outside the skew window.
+  pub out_of_window: AtomicU64,
+}
+impl Replay {
+  pub fn init(&self, window: Duration, capacity: usize) {
+    self.window.init(window);
+    self.capacity.init(capacity);
+  }
+
+  /// Records the packet, failing if it is a replay. Packets from bridges that
+  /// do not stamp them yet are let through.
+  pub fn check(&self, opened: &Opened) -> Result<()> {
+    let (Some(ts), Some(id)) = (opened.ts, &opened.id) else {
+      return Ok(());
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    if now.abs_diff(ts) > self.window.as_millis() as u64 {
+      self.out_of_window.fetch_add(1, Ordering::Relaxed);
+      bail!(
+        "Dropped a packet sealed {}ms away from the local clock",
+        now.abs_diff(ts)
+      );
+    }
+    let mut seen = self.seen.lock().unwrap();
+    if !seen.ids.insert(id.clone()) {
+      self.duplicates.fetch_add(1, Ordering::Relaxed);
+      bail!("Dropped a replayed packet {}", hex::encode(id));
+    }
+    seen.order.push_back(id.clone());
+    while seen.order.len() > *self.capacity {
+      if let Some(oldest) = seen.order.pop_front() {
+        seen.ids.remove(&oldest);
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::{
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+  };
+
+  use crate::{
+    data::{events::Event, Opened},
+    replay::Replay,
+  };
+  fn opened(ts: u64, id: u8) -> Opened {
+    Opened {
+      payload: Event::RequestEcho { name: "".into() }.into(),
+      sender: None,
+      ts: Some(ts),
+      id: Some(vec![id; 16]),
+    }
+  }
+  #[test]
+  fn test() {
+    let replay = Replay::default();
+    replay.init(Duration::from_secs(60), 2);
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_millis() as u64;
+
+    assert!(replay.check(&opened(now, 1)).is_ok());
+    assert!(replay.check(&opened(now, 1)).is_err());
+    assert_eq!(replay.duplicates.load(Ordering::Relaxed), 1);
+    assert!(replay.check(&opened(now - 120_000, 2)).is_err());
+    assert_eq!(replay.out_of_window.load(Ordering::Relaxed), 1);
+
+    assert!(replay.check(&opened(now, 2)).is_ok());
+    assert!(replay.check(&opened(now, 3)).is_ok());
+    // the oldest id got evicted
+    assert!(replay.check(&opened(now, 1)).is_ok());
+  }
+}
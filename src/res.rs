@@ -1,38 +1,51 @@
-use std::{panic, path::PathBuf, time::Duration};
+use std::{path::PathBuf, sync::Weak, time::Duration};
 
 use arcstr::ArcStr;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, Result};
 use dashmap::DashMap;
+use futures_util::future::select_ok;
 use lateinit::LateInit;
+use once_cell::sync::Lazy;
 use sled::IVec;
 use tokio::{sync::oneshot, task::JoinHandle, time::timeout};
 use tracing::trace;
 use uuid::Uuid;
 
 use crate::{
-  data::{events::Event, Packet},
-  db::DB,
-  net::NET,
-  server::SERVER,
+  cipher::{open_file, seal_file},
+  client::{upgrade, MesagistoClient, DEFAULT_CLIENT},
+  data::{events::Event, message::FileSecret, Packet, Payload},
   ResultExt,
 };
 
-#[derive(Singleton, Default)]
+pub static RES: Lazy<&'static Res> = Lazy::new(|| &DEFAULT_CLIENT.res);
+
+#[derive(Default)]
 pub struct Res {
+  client: Weak<MesagistoClient>,
   pub directory: LateInit<PathBuf>,
   pub handlers: DashMap<ArcStr, Vec<oneshot::Sender<PathBuf>>>,
   handle: LateInit<JoinHandle<()>>,
 }
 impl Res {
-  pub async fn init(&self) {
+  pub fn new(client: Weak<MesagistoClient>) -> Self {
+    Self {
+      client,
+      ..Default::default()
+    }
+  }
+
+  pub async fn init(&self, name: &ArcStr) {
     let path = {
       let mut dir = std::env::temp_dir();
       dir.push("mesagisto");
+      // networks must not see each other's files
+      dir.push(name.as_str());
       dir
     };
     tokio::fs::create_dir_all(path.as_path()).await.unwrap();
     self.directory.init(path);
-    RES.poll().await;
+    self.poll().await;
   }
 
   pub fn get(&self, name: &ArcStr) -> Option<PathBuf> {
@@ -45,18 +58,23 @@ impl Res {
   }
 
   async fn poll(&self) {
-    let handle: JoinHandle<_> = tokio::spawn(async {
+    let owner = self.client.clone();
+    let handle: JoinHandle<_> = tokio::spawn(async move {
       let mut interval = tokio::time::interval(Duration::from_millis(200));
       loop {
+        let Some(owner) = owner.upgrade() else {
+          break;
+        };
+        let res = &owner.res;
         let mut for_remove = vec![];
-        for entry in &RES.handlers {
-          let path = RES.path(entry.key());
+        for entry in &res.handlers {
+          let path = res.path(entry.key());
           if path.exists() {
             for_remove.push((entry.key().to_owned(), path));
           }
         }
         for_remove.into_iter().for_each(|v| {
-          if let Some((.., handler_list)) = RES.handlers.remove(&v.0) {
+          if let Some((.., handler_list)) = res.handlers.remove(&v.0) {
             for handler in handler_list {
               handler.send(v.1.to_owned()).log();
             }
@@ -92,70 +110,129 @@ impl Res {
     U: AsRef<[u8]>,
     F: Into<IVec>,
   {
-    DB.put_image_id(uid, file_id);
+    upgrade(&self.client).db.put_image_id(uid, file_id);
   }
 
   pub async fn file(
     &self,
     id: &Vec<u8>,
     url: &Option<ArcStr>,
+    secret: &Option<FileSecret>,
     room: &Uuid,
     server: &ArcStr,
   ) -> Result<PathBuf> {
     match url {
-      Some(url) => self.file_by_url(id, url).await,
-      None => self.file_by_uid(id, room, server).await,
+      Some(url) => self.file_by_url(id, url, secret).await,
+      None => self.file_by_uid(id, secret, room, server).await,
     }
   }
 
-  pub async fn file_by_uid(&self, uid: &Vec<u8>, room: &Uuid, server: &ArcStr) -> Result<PathBuf> {
-    use crate::data::Payload;
+  pub async fn file_by_uid(
+    &self,
+    uid: &Vec<u8>,
+    secret: &Option<FileSecret>,
+    room: &Uuid,
+    server: &ArcStr,
+  ) -> Result<PathBuf> {
     let uid_str: ArcStr = base64_url::encode(uid).into();
     trace!("Caching file by uid {}", uid_str);
-    let path = RES.path(&uid_str);
+    let path = self.path(&uid_str);
     if path.exists() {
       trace!("File exists,return the path");
       return Ok(path);
     }
-    let tmp_path = RES.tmp_path(&uid_str);
+    let tmp_path = self.tmp_path(&uid_str);
     if tmp_path.exists() {
       trace!("TmpFile exists,waiting for the file downloading");
-      return RES.wait_for(&uid_str).await;
+      return self.wait_for(&uid_str).await;
     }
-    trace!("TmpFile dont exist,requesting image url");
-    let event: Event = Event::RequestImage { id: uid.clone() };
-    // fixme error handling
-    let packet = Packet::new(room.to_owned(), event.into())?;
-    // fixme timeout check
-    let packet = timeout(Duration::from_secs(7), SERVER.request(packet, server)).await??;
-
-    match packet.decrypt()? {
-      Payload::EventPayload(Event::RespondImage { id, url }) => self.file_by_url(&id, &url).await,
-      _ => panic!("Not correct response"),
+    trace!("TmpFile dont exist,requesting file url");
+    let client = upgrade(&self.client);
+    // bridges predating RequestResource cannot decode it and stay silent,
+    // they only ever served images, so both are asked at once
+    let requests = [
+      Event::RequestResource { id: uid.clone() },
+      Event::RequestImage { id: uid.clone() },
+    ]
+    .map(|event| Box::pin(self.request_url(&client, event, room, server)));
+    let ((id, url), _) = select_ok(requests).await?;
+    self.file_by_url(&id, &url, secret).await
+  }
+
+  /// Sends a resource request and waits for the answer carrying the url.
+  async fn request_url(
+    &self,
+    client: &MesagistoClient,
+    event: Event,
+    room: &Uuid,
+    server: &ArcStr,
+  ) -> Result<(Vec<u8>, ArcStr)> {
+    let packet = Packet::new_with(client, room.to_owned(), event.into())?;
+    let Ok(packet) = timeout(
+      Duration::from_secs(7),
+      client.server.request(packet, server),
+    )
+    .await
+    else {
+      bail!("No bridge answered the resource request");
+    };
+    match packet?.decrypt_with(client)? {
+      Payload::EventPayload(event) => match event.into_resource_response() {
+        Some(response) => Ok(response),
+        None => bail!("Not correct response"),
+      },
+      _ => bail!("Not correct response"),
     }
   }
 
-  pub async fn file_by_url(&self, id: &Vec<u8>, url: &ArcStr) -> Result<PathBuf> {
+  /// Downloads the file, decrypting and verifying it first when it was
+  /// uploaded encrypted.
+  pub async fn file_by_url(
+    &self,
+    id: &Vec<u8>,
+    url: &ArcStr,
+    secret: &Option<FileSecret>,
+  ) -> Result<PathBuf> {
     let id_str: ArcStr = base64_url::encode(id).into();
-    let path = RES.path(&id_str);
+    let path = self.path(&id_str);
     if path.exists() {
       return Ok(path);
     }
 
-    let tmp_path = RES.tmp_path(&id_str);
+    let tmp_path = self.tmp_path(&id_str);
     if tmp_path.exists() {
-      Ok(RES.wait_for(&id_str).await?)
+      Ok(self.wait_for(&id_str).await?)
     } else {
       // fixme error handling
-      NET.download(url, &tmp_path).await?;
+      upgrade(&self.client).net.download(url, &tmp_path).await?;
+      if let Some(secret) = secret {
+        let sealed = tokio::fs::read(&tmp_path).await?;
+        match open_file(secret, &sealed) {
+          Ok(plain) => tokio::fs::write(&tmp_path, plain).await?,
+          Err(e) => {
+            tokio::fs::remove_file(&tmp_path).await?;
+            return Err(e);
+          }
+        }
+      }
       tokio::fs::rename(&tmp_path, &path).await?;
       Ok(path)
     }
   }
 
+  /// Writes an encrypted copy of `file` next to the cached files, ready to be
+  /// uploaded or served. The secret goes into the message segment.
+  pub async fn seal_file(&self, id: &Vec<u8>, file: &PathBuf) -> Result<(PathBuf, FileSecret)> {
+    let (sealed, secret) = seal_file(&tokio::fs::read(file).await?)?;
+    let mut path = self.directory.clone();
+    path.push(format!("{}.sealed", base64_url::encode(id)));
+    tokio::fs::write(&path, sealed).await?;
+    Ok((path, secret))
+  }
+
   pub async fn put_file(&self, id: &Vec<u8>, file: &PathBuf) -> Result<PathBuf> {
     let id_str: ArcStr = base64_url::encode(id).into();
-    let path = RES.path(&id_str);
+    let path = self.path(&id_str);
     tokio::fs::rename(&file, &path).await?;
     Ok(path)
   }
@@ -0,0 +1,107 @@
+use std::{
+  fmt::{self, Debug, Display, Formatter},
+  path::{Path, PathBuf},
+};
+
+use arcstr::ArcStr;
+use color_eyre::eyre::{Result, WrapErr};
+use serde::{Deserialize, Deserializer};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// String holding key material. It is wiped from memory once dropped and
+/// never shows up in `Debug` or `Display` output.
+///
+/// When deserialized it accepts the secret itself, `{ env = "VAR" }` or
+/// `{ file = "path" }`, so configs do not need to embed it.
+#[derive(Clone, Default, Zeroize, ZeroizeOnDrop)]
+pub struct Secret(String);
+
+impl Secret {
+  pub fn new<S: Into<String>>(secret: S) -> Self {
+    Self(secret.into())
+  }
+
+  pub fn from_env(var: &str) -> Result<Self> {
+    let secret =
+      std::env::var(var).wrap_err_with(|| format!("Failed to read secret from ${}", var))?;
+    Ok(Self(secret))
+  }
+
+  /// Reads the whole file, dropping a trailing line break.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref();
+    let mut secret = std::fs::read_to_string(path)
+      .wrap_err_with(|| format!("Failed to read secret from {}", path.display()))?;
+    let len = secret.trim_end_matches(['\r', '\n']).len();
+    secret.truncate(len);
+    Ok(Self(secret))
+  }
+
+  pub fn expose(&self) -> &str {
+    &self.0
+  }
+}
+
+impl Debug for Secret {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str("Secret(***)")
+  }
+}
+
+impl Display for Secret {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str("***")
+  }
+}
+
+impl From<String> for Secret {
+  fn from(value: String) -> Self {
+    Self(value)
+  }
+}
+impl From<&str> for Secret {
+  fn from(value: &str) -> Self {
+    Self(value.to_owned())
+  }
+}
+impl From<ArcStr> for Secret {
+  fn from(value: ArcStr) -> Self {
+    Self(value.to_string())
+  }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Source {
+      Inline(String),
+      Env { env: String },
+      File { file: PathBuf },
+    }
+    match Source::deserialize(deserializer)? {
+      Source::Inline(secret) => Ok(Self(secret)),
+      Source::Env { env } => Self::from_env(&env).map_err(serde::de::Error::custom),
+      Source::File { file } => Self::from_file(file).map_err(serde::de::Error::custom),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::secret::Secret;
+  #[test]
+  fn test() {
+    let secret = Secret::from("this is key");
+    assert_eq!(secret.expose(), "this is key");
+    assert_eq!(format!("{:?} {}", secret, secret), "Secret(***) ***");
+
+    let path = std::env::temp_dir().join("mesagisto-secret-test");
+    std::fs::write(&path, "from file\n").unwrap();
+    assert_eq!(Secret::from_file(&path).unwrap().expose(), "from file");
+    std::fs::remove_file(&path).unwrap();
+  }
+}
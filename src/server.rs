@@ -1,6 +1,6 @@
 use std::sync::{
   atomic::{AtomicI64, Ordering},
-  Arc,
+  Arc, Weak,
 };
 
 use arcstr::ArcStr;
@@ -9,6 +9,7 @@ use color_eyre::eyre::Result;
 use dashmap::DashMap;
 use futures_util::{future::BoxFuture, StreamExt};
 use lateinit::LateInit;
+use once_cell::sync::Lazy;
 use tokio::task::JoinHandle;
 use tracing::instrument;
 use uuid::Uuid;
@@ -18,10 +19,17 @@ use crate::{OkExt, ResultExt};
 pub trait PacketHandler =
   Fn(Packet) -> BoxFuture<'static, Result<ControlFlow<Packet>>> + Send + Sync + 'static;
 
-use crate::{cipher::CIPHER, data::Packet, ControlFlow, NAMESPACE_MSGIST};
+use crate::{
+  client::{upgrade, MesagistoClient, DEFAULT_CLIENT},
+  data::Packet,
+  ControlFlow,
+};
+
+pub static SERVER: Lazy<&'static Server> = Lazy::new(|| &DEFAULT_CLIENT.server);
 
-#[derive(Singleton, Default)]
+#[derive(Default)]
 pub struct Server {
+  client: Weak<MesagistoClient>,
   pub conn: LateInit<nats::Client>,
   pub remote_address: LateInit<ArcStr>,
   pub packet_handler: LateInit<Box<dyn PacketHandler>>,
@@ -30,6 +38,13 @@ pub struct Server {
   pub subs: DashMap<Uuid, (AtomicI64, JoinHandle<()>)>,
 }
 impl Server {
+  pub fn new(client: Weak<MesagistoClient>) -> Self {
+    Self {
+      client,
+      ..Default::default()
+    }
+  }
+
   pub async fn init(&self, remote_address: Option<ArcStr>) -> Result<()> {
     let remote_address = remote_address.unwrap_or("itsusinn.site:4222".into());
 
@@ -44,8 +59,7 @@ impl Server {
   pub fn room_id(&self, room_address: ArcStr) -> Uuid {
     let entry = self.room_map.entry(room_address.clone());
     *entry.or_insert_with(|| {
-      let unique_address = format!("{}{}", room_address, *CIPHER.origin_key);
-      Uuid::new_v5(&NAMESPACE_MSGIST, unique_address.as_bytes())
+      upgrade(&self.client).cipher.room_id(&room_address)
     })
   }
 
@@ -63,6 +77,7 @@ impl Server {
   pub async fn sub(&self, room_id: Uuid) -> Result<()> {
 
       let client = self.conn.clone();
+      let owner = self.client.clone();
       let subs = self
         .subs
         .entry(room_id.to_owned())
@@ -73,12 +88,24 @@ impl Server {
               .await
               .expect("Failed to subscribe");
             while let Some(next) = sub.next().await {
-              let pkt = Packet {
+              let Some(owner) = owner.upgrade() else {
+                break;
+              };
+              let mut pkt = Packet {
                 content: next.payload.to_vec(),
                 room_id,
                 reply: next.reply,
+                opened: None,
+              };
+              let opened = pkt.open_uncached(&owner);
+              let Some(opened) = opened.and_then(|opened| {
+                owner.replay.check(&opened)?;
+                Ok(opened)
+              }).log() else {
+                continue;
               };
-              (SERVER.packet_handler)(pkt).await.log();
+              pkt.opened = Some(opened);
+              (owner.server.packet_handler)(pkt).await.log();
             }
           });
           (AtomicI64::new(0), handle)
@@ -115,6 +142,7 @@ impl Server {
         content: msg.payload.into(),
         room_id: pkt.room_id,
         reply: None,
+        opened: None,
       }
       .ok()
 
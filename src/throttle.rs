@@ -0,0 +1,114 @@
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use lateinit::LateInit;
+use once_cell::sync::Lazy;
+use uuid::Uuid;
+
+use crate::{client::DEFAULT_CLIENT, data::events::Event};
+
+pub static THROTTLE: Lazy<&'static Throttle> = Lazy::new(|| &DEFAULT_CLIENT.throttle);
+
+/// Holds back typing and presence events that only repeat what was sent for
+/// the same user moments ago, chatty platforms resend them every few seconds.
+#[derive(Default)]
+pub struct Throttle {
+  pub interval: LateInit<Duration>,
+  last: DashMap<(Uuid, Vec<u8>), (u8, Instant)>,
+  pruned_at: Mutex<Option<Instant>>,
+}
+impl Throttle {
+  pub fn init(&self, interval: Duration) {
+    self.interval.init(interval);
+  }
+
+  /// Whether `event` should be sent to `room`. Changes of state always go
+  /// through, other events are never held back.
+  pub fn allow(&self, room: &Uuid, event: &Event) -> bool {
+    let (kind, profile, state) = match event {
+      Event::Typing { profile, typing } => (b't', profile, *typing as u8),
+      Event::Presence { profile, status } => (b'p', profile, *status as u8),
+      _ => return true,
+    };
+    let mut key = vec![kind];
+    key.extend_from_slice(&profile.id);
+    let now = Instant::now();
+    self.prune(now);
+    match self.last.entry((*room, key)) {
+      Entry::Occupied(mut last) => {
+        let (last_state, at) = *last.get();
+        if last_state == state && now.duration_since(at) < *self.interval {
+          return false;
+        }
+        last.insert((state, now));
+      }
+      Entry::Vacant(last) => {
+        last.insert((state, now));
+      }
+    }
+    true
+  }
+
+  /// Forgets events older than the interval, they hold nothing back anymore.
+  /// Runs at most once per interval.
+  fn prune(&self, now: Instant) {
+    let mut pruned_at = self.pruned_at.lock().unwrap();
+    if matches!(*pruned_at, Some(at) if now.duration_since(at) < *self.interval) {
+      return;
+    }
+    *pruned_at = Some(now);
+    self
+      .last
+      .retain(|_, (_, at)| now.duration_since(*at) < *self.interval);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use uuid::Uuid;
+
+  use crate::{
+    data::{events::Event, message::Profile},
+    throttle::Throttle,
+  };
+  fn typing(typing: bool) -> Event {
+    Event::Typing {
+      profile: Profile {
+        id: vec![1],
+        username: None,
+        nick: None,
+      },
+      typing,
+    }
+  }
+  #[test]
+  fn test() {
+    let room = Uuid::new_v4();
+    let throttle = Throttle::default();
+    throttle.init(Duration::from_secs(60));
+
+    assert!(throttle.allow(&room, &typing(true)));
+    assert!(!throttle.allow(&room, &typing(true)));
+    assert!(throttle.allow(&Uuid::new_v4(), &typing(true)));
+    assert!(throttle.allow(&room, &typing(false)));
+    assert!(throttle.allow(&room, &typing(true)));
+  }
+
+  #[test]
+  fn test_prune() {
+    let throttle = Throttle::default();
+    throttle.init(Duration::from_millis(200));
+    for _ in 0..100 {
+      assert!(throttle.allow(&Uuid::new_v4(), &typing(true)));
+    }
+    assert_eq!(throttle.last.len(), 100);
+    std::thread::sleep(Duration::from_millis(250));
+    assert!(throttle.allow(&Uuid::new_v4(), &typing(true)));
+    assert_eq!(throttle.last.len(), 1);
+  }
+}